@@ -36,7 +36,7 @@ pub extern "C" fn Java_com_bwqr_bevyedge_InputKt__1init(
 
     log::init();
 
-    input::init("10.0.2.2:4001".to_string());
+    input::init(None);
 
     ::log::info!("bevyedge is built with {} profile", if cfg!(debug_assertions) { "debug" } else { "release" });
     ::log::info!("bevyedge runtime is initialized");
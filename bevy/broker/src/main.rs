@@ -0,0 +1,184 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use clap::Parser;
+use log::{debug, error, warn, LevelFilter};
+use shared::codec::NoneCodec;
+use shared::frame::MessageKind;
+use shared::handshake::{self, Handshake, Rejection};
+use shared::registry::{Registry, REGISTRY_PORT};
+use shared::settings::{PhysicsPlugin, Settings};
+use shared::transport::{Listener, TlsConfig, Transport, TransportKind};
+
+/// Routes bevy-edge clients to the least loaded of the physics servers registered with it.
+#[derive(Parser)]
+struct Args {
+    #[arg(long, default_value_t = IpAddr::V4(Ipv4Addr::UNSPECIFIED))]
+    bind: IpAddr,
+    /// Port of TCP, or of TLS if a certificate is given, and of UDP without one.
+    #[arg(long, default_value_t = 4001)]
+    port: u16,
+    /// Port of QUIC, the one after --port if not given.
+    #[arg(long)]
+    quic_port: Option<u16>,
+    #[arg(long, default_value_t = REGISTRY_PORT)]
+    registry_port: u16,
+    /// Certificate to serve TLS instead of plain TCP and UDP, and QUIC with, in PEM.
+    #[arg(long, requires = "key")]
+    certificate: Option<String>,
+    /// Private key of the certificate, in PEM.
+    #[arg(long, requires = "certificate")]
+    key: Option<String>,
+    /// Token the physics servers register with and the clients present in their settings.
+    #[arg(long)]
+    token: Option<String>,
+    /// Level of the logs, overrides RUST_LOG.
+    #[arg(long)]
+    log_level: Option<LevelFilter>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    shared::init_logger(args.log_level);
+
+    let registry_address = SocketAddr::new(args.bind, args.registry_port).to_string();
+    let registry = Registry::bind(&registry_address, args.token.as_deref()).unwrap();
+
+    if args.token.is_none() {
+        warn!("no token is given, any host can register a server and have clients routed to it");
+    }
+
+    let address = SocketAddr::new(args.bind, args.port).to_string();
+    let quic_address = SocketAddr::new(args.bind, args.quic_port.unwrap_or_else(|| args.port.checked_add(1).expect("there is no port after --port for quic, give --quic-port"))).to_string();
+    let tls = args.certificate.zip(args.key).map(|(certificate, key)| TlsConfig { certificate, key });
+
+    let srv = Listener::new();
+    match &tls {
+        // As on the physics server, UDP would carry the settings and their token in the clear.
+        Some(tls) => srv.tls(&address, tls).unwrap(),
+        None => {
+            srv.tcp(&address).unwrap();
+            srv.udp(&address).unwrap();
+        }
+    }
+    srv.quic(&quic_address, tls.as_ref()).unwrap();
+
+    debug!("listening on {address}, quic on {quic_address}, registrations on {registry_address}");
+
+    loop {
+        let transport = match srv.accept() {
+            Ok(transport) => transport,
+            Err(e) => {
+                error!("failed to accept a client, {e}");
+                continue;
+            }
+        };
+
+        let registry = registry.clone();
+        let token = args.token.clone();
+        std::thread::spawn(move || route(transport, &registry, token.as_deref()));
+    }
+}
+
+/// Answers the settings of the client with the server it should start its session on, once the
+/// client presents the token.
+fn route(mut transport: Box<dyn Transport>, registry: &Registry, token: Option<&str>) {
+    let settings: Settings = match transport.recv(MessageKind::Settings, &mut NoneCodec) {
+        Ok((settings, _)) => settings,
+        Err(e) => {
+            error!("failed to receive settings, {e}");
+            return;
+        }
+    };
+
+    let (given, kind) = match &settings.physics_plugin {
+        PhysicsPlugin::Server { checksum, token, transport: kind, .. } => {
+            transport.set_checksum(*checksum);
+            (token.as_deref(), kind.clone())
+        }
+        PhysicsPlugin::Default => (None, TransportKind::Tcp),
+    };
+
+    let handshake = match handshake::authenticate(token, given).map(|()| registry.route()) {
+        Ok(Some(server)) => {
+            let address = server.address(&kind);
+            debug!("routing client connected over {} to {address}", transport.name());
+
+            Handshake::Redirect { address }
+        }
+        Ok(None) => {
+            warn!("no registered server can take the client connected over {}", transport.name());
+
+            Handshake::Rejected(Rejection::Busy)
+        }
+        Err(rejection) => {
+            warn!("rejecting client connected over {}, {rejection}", transport.name());
+
+            Handshake::Rejected(rejection)
+        }
+    };
+
+    if let Err(e) = transport.send(MessageKind::Handshake, &mut NoneCodec, &handshake) {
+        error!("failed to answer the client, {e}");
+    }
+
+    let _ = transport.close();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use shared::discovery::{Announcement, Service};
+    use shared::registry;
+    use shared::transport;
+
+    use super::*;
+
+    fn settings(token: &str) -> Settings {
+        ron::from_str(&format!(r#"(
+            tracing_level: None,
+            headless: true,
+            physics_plugin: Server(codec: None, address: "broker", transport: Quic(certificate: None), token: Some("{token}")),
+            bench_length: 1.0,
+            scene: (camera: (0.0, 0.0, 0.0), num_object: 1, shape: "ball", restitution: 0.0, room: Open, ccd: false),
+        )"#)).unwrap()
+    }
+
+    fn routed(registry: &Registry, settings: &Settings) -> Handshake {
+        let (mut client, server) = transport::channel();
+        client.send(MessageKind::Settings, &mut NoneCodec, settings).unwrap();
+        route(server, registry, Some("secret"));
+
+        client.recv(MessageKind::Handshake, &mut NoneCodec).unwrap().0
+    }
+
+    #[test]
+    fn clients_with_the_token_are_redirected_to_a_registered_server() {
+        let address = "127.0.0.1:47132";
+        let registry = Registry::bind(address, Some("secret")).unwrap();
+
+        registry::register(address.to_string(), Some("10.0.0.7".to_string()), Some("secret".to_string()), || Announcement {
+            service: Service::Physics,
+            port: 4001,
+            quic_port: Some(4500),
+            tls: false,
+            token_required: true,
+            active_sessions: 0,
+            max_sessions: 4,
+        });
+
+        let started = Instant::now();
+        while registry.servers().is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(5), "server did not register");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        match routed(&registry, &settings("secret")) {
+            Handshake::Redirect { address } => assert_eq!(address, "10.0.0.7:4500"),
+            _ => panic!("client with the token was not redirected"),
+        }
+
+        assert!(matches!(routed(&registry, &settings("guess")), Handshake::Rejected(Rejection::InvalidToken)));
+    }
+}
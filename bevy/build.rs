@@ -0,0 +1,6 @@
+fn main() {
+    // The profile the client is built with, for the metadata of the bench. Cargo only tells it to
+    // build scripts.
+    println!("cargo:rustc-env=BUILD_PROFILE={}", std::env::var("PROFILE").unwrap());
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -1,18 +1,23 @@
-use std::{collections::HashMap, sync::RwLock, io::Read};
+use std::{collections::HashMap, sync::RwLock, io::Read, time::Duration};
 
 use bevy_input::{prelude::KeyCode, ButtonState};
 use once_cell::sync::OnceCell;
+use shared::{discovery::{self, Service}, transport::TransportKind};
 
 static KEY_EVENTS: OnceCell<RwLock<HashMap<KeyCode, ButtonState>>> = OnceCell::new();
 
-pub fn init(address: String) {
+/// How long to collect the answers of the games when no address is given.
+const DISCOVERY_WAIT: Duration = Duration::from_millis(500);
+
+/// Sends the key events to the game at the address, or to the one discovered on the local network.
+pub fn init(address: Option<String>) {
     KEY_EVENTS.set(RwLock::new(HashMap::new())).unwrap();
 
     std::thread::spawn(move || {
-        while let Err(e) = send_key_events(address.as_str()) {
+        while let Err(e) = resolve(&address).and_then(|address| send_key_events(&address)) {
             log::error!("{e}");
 
-            std::thread::sleep(std::time::Duration::from_secs(2));
+            std::thread::sleep(Duration::from_secs(2));
         }
     });
 }
@@ -48,6 +53,17 @@ pub fn key_code_from_i32(value: i32) -> KeyCode {
     }
 }
 
+fn resolve(address: &Option<String>) -> Result<String, String> {
+    if let Some(address) = address {
+        return Ok(address.clone());
+    }
+
+    discovery::pick(Service::Input, DISCOVERY_WAIT)
+        .map_err(|e| format!("failed to discover a game, {e:?}"))?
+        .map(|game| game.address(&TransportKind::Tcp))
+        .ok_or_else(|| "no game answered the discovery probe".to_string())
+}
+
 fn send_key_events(address: &str) -> Result<(), String> {
     let mut stream = std::net::TcpStream::connect(address)
         .map_err(|e| format!("failed to connect server, {e:?}"))?;
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use clap::Parser;
+use log::{debug, warn, LevelFilter};
+use physics::{Config, Migrations};
+use shared::codec::{Codec, NoneCodec};
+use shared::frame::MessageKind;
+use shared::handshake::Handshake;
+use shared::recording::{Message, Recording};
+use shared::request::Request;
+use shared::response::{Log, Response, SyncContext};
+use shared::settings::{PhysicsPlugin, Settings};
+use shared::transport::{self, Transport, TransportKind};
+
+/// Feeds the requests of a recorded session to a fresh physics server and compares its responses
+/// with the recorded ones.
+#[derive(Parser)]
+struct Args {
+    recording: PathBuf,
+    /// Server to replay against over TCP, one running in this process if not given.
+    #[arg(long)]
+    address: Option<String>,
+    /// Token the server at --address requires, recordings leave it out.
+    #[arg(long)]
+    token: Option<String>,
+    /// How far a transform may drift from the recorded one before its frame diverges, in meters for the
+    /// translations and as the distance between the unit quaternions for the rotations.
+    #[arg(long, default_value_t = 1e-4)]
+    tolerance: f32,
+    /// Send the requests as far apart as they were recorded instead of as soon as the server answers.
+    #[arg(long)]
+    paced: bool,
+    /// Level of the logs, overrides RUST_LOG.
+    #[arg(long)]
+    log_level: Option<LevelFilter>,
+}
+
+/// How far the replayed transforms of a frame are from the recorded ones.
+#[derive(Default)]
+struct Drift {
+    translation: f32,
+    rotation: f32,
+    missing: usize,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    shared::init_logger(args.log_level);
+
+    let mut recording = Recording::open(&args.recording)
+        .unwrap_or_else(|e| fail(format!("failed to open the recording {}, {e}", args.recording.display())));
+
+    // A single server simulates the whole world, wherever the recorded session ran.
+    let mut settings = recording.settings.clone();
+    if let PhysicsPlugin::Server { partition, migration, resume, record, token, .. } = &mut settings.physics_plugin {
+        if partition.take().is_some() {
+            warn!("the session was split among servers, the replay runs on one and may drift at the boundaries");
+        }
+        *migration = None;
+        *resume = None;
+        *record = None;
+        *token = args.token.clone();
+    }
+
+    let (mut link, server) = match &args.address {
+        Some(address) => {
+            let link = transport::connect(&TransportKind::Tcp, address)
+                .unwrap_or_else(|e| fail(format!("failed to connect to {address}, {e}")));
+            (link, None)
+        }
+        None => {
+            let (client, server) = transport::channel();
+            (client, Some(std::thread::spawn(move || physics::serve(server, &Config::default(), &Migrations::default()))))
+        }
+    };
+    let mut codec = handshake(link.as_mut(), &settings);
+
+    let started = Instant::now();
+    let mut replayed: Option<SyncContext> = None;
+    let (mut frames, mut diverged) = (0, 0);
+    let mut worst = Drift::default();
+    let mut recorded_length = std::time::Duration::ZERO;
+    let mut shut_down = false;
+
+    for entry in &mut recording {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                warn!("recording ends in the middle of a message, replaying the ones before it");
+                break;
+            }
+            Err(e) => fail(format!("failed to read the recording, {e}")),
+        };
+        recorded_length = entry.at;
+
+        match entry.message {
+            // Only writes a file on the server.
+            Message::Request(Request::Snapshot) => {}
+            Message::Request(request) => {
+                if args.paced {
+                    std::thread::sleep(entry.at.saturating_sub(started.elapsed()));
+                }
+
+                link.send(MessageKind::Request, codec.as_mut(), &request)
+                    .unwrap_or_else(|e| fail(format!("failed to send frame {frames}, {e}")));
+
+                if let Request::Shutdown = request {
+                    shut_down = true;
+                    break;
+                }
+
+                let (Response::SyncContext(ctx), _) = link
+                    .recv(MessageKind::Response, codec.as_mut())
+                    .unwrap_or_else(|e| fail(format!("failed to receive frame {frames}, {e}")));
+                let _: (Log, _) = link
+                    .recv(MessageKind::Log, &mut NoneCodec)
+                    .unwrap_or_else(|e| fail(format!("failed to receive the log of frame {frames}, {e}")));
+                replayed = Some(ctx);
+            }
+            Message::Response(Response::SyncContext(recorded)) => {
+                let replayed = replayed.take().unwrap_or_else(|| fail(format!("recording has a response without a request at frame {frames}")));
+                let drift = compare(&recorded, &replayed);
+
+                let handles_differ = recorded.rigid_body_handles.len() != replayed.rigid_body_handles.len()
+                    || recorded.collider_handles.len() != replayed.collider_handles.len();
+
+                if handles_differ || drift.missing > 0 || drift.translation > args.tolerance || drift.rotation > args.tolerance {
+                    if diverged == 0 {
+                        warn!("replay diverges from the recording at frame {frames}");
+                    }
+                    debug!(
+                        "frame {frames} drifts {} m and {} in rotation, {} transforms missing, handles differ {handles_differ}",
+                        drift.translation, drift.rotation, drift.missing,
+                    );
+                    diverged += 1;
+                }
+
+                worst.translation = worst.translation.max(drift.translation);
+                worst.rotation = worst.rotation.max(drift.rotation);
+                worst.missing = worst.missing.max(drift.missing);
+                frames += 1;
+            }
+        }
+    }
+
+    // A recording of an app that did not exit cleanly ends without a shutdown.
+    if !shut_down {
+        if let Err(e) = link.send(MessageKind::Request, codec.as_mut(), &Request::Shutdown) {
+            warn!("failed to shut the session down, {e}");
+        }
+    }
+    if let Err(e) = link.close() {
+        warn!("failed to close the link, {e}");
+    }
+    if server.is_some_and(|server| server.join().is_err()) {
+        fail("the physics server panicked".to_string());
+    }
+
+    println!(
+        "{frames} frames replayed in {:.3} s, recorded in {:.3} s, {diverged} diverged, drift at most {} m and {} in rotation, {} transforms missing at most",
+        started.elapsed().as_secs_f32(), recorded_length.as_secs_f32(), worst.translation, worst.rotation, worst.missing,
+    );
+
+    if diverged > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn handshake(link: &mut dyn Transport, settings: &Settings) -> Box<dyn Codec> {
+    if let PhysicsPlugin::Server { checksum, .. } = &settings.physics_plugin {
+        link.set_checksum(*checksum);
+    }
+
+    link.send(MessageKind::Settings, &mut NoneCodec, settings)
+        .unwrap_or_else(|e| fail(format!("failed to send the settings, {e}")));
+
+    let handshake = link.recv(MessageKind::Handshake, &mut NoneCodec)
+        .unwrap_or_else(|e| fail(format!("failed to receive the handshake, {e}")));
+
+    match handshake {
+        (Handshake::Accepted { codec, codec_context }, _) => codec.build(codec_context),
+        (Handshake::Redirect { address }, _) => fail(format!("replay against the server {address} the broker routes to instead")),
+        (Handshake::Rejected(rejection), _) => fail(format!("physics server rejected the replay, {rejection}")),
+    }
+}
+
+fn compare(recorded: &SyncContext, replayed: &SyncContext) -> Drift {
+    let replayed: HashMap<_, _> = replayed.transforms.iter().map(|(id, transform)| (*id, transform)).collect();
+    let mut drift = Drift::default();
+
+    for (id, recorded) in &recorded.transforms {
+        match replayed.get(id) {
+            Some(replayed) => {
+                drift.translation = drift.translation.max(recorded.translation.distance(replayed.translation));
+                // The quaternion and its negation are the same rotation.
+                let rotation = (recorded.rotation - replayed.rotation).length().min((recorded.rotation + replayed.rotation).length());
+                drift.rotation = drift.rotation.max(rotation);
+            }
+            None => drift.missing += 1,
+        }
+    }
+
+    drift
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
+}
@@ -0,0 +1,106 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+use log::LevelFilter;
+use serde::{de::Error, Deserialize, Deserializer};
+use shared::codec::{CodecContext, CodecKind};
+use shared::transport::TlsConfig;
+
+/// How the server picks the codec of a session.
+#[derive(Clone, Copy, Default, Deserialize)]
+pub enum CodecPolicy {
+    /// Runs the codec the client asks for, as far as the codec supports it.
+    #[default]
+    Client,
+    /// Runs this codec whatever the client asks for.
+    Server {
+        codec: CodecKind,
+        #[serde(default)]
+        codec_context: CodecContext,
+    },
+}
+
+/// Configuration of the server itself, as opposed to the settings every client sends.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub bind: IpAddr,
+    /// Port of TCP or TLS and UDP.
+    pub port: u16,
+    /// Port of QUIC, the one after [`Config::port`] if not given.
+    pub quic_port: Option<u16>,
+    /// File shared with clients on the same host, the one in the temporary directory named after
+    /// [`Config::port`] if not given. It must not exist when the server starts.
+    pub shm: Option<PathBuf>,
+    /// Sessions served at the same time, further clients are rejected until one finishes.
+    pub max_sessions: usize,
+    /// Sessions to serve before exiting, 0 to keep serving.
+    pub sessions: usize,
+    /// Answer discovery probes of the clients on the local network.
+    pub discovery: bool,
+    /// Port the sessions handed over by other servers arrive on, usually
+    /// [`shared::migration::MIGRATION_PORT`]. Handovers are not accepted if not given, and only
+    /// from the servers sharing [`Config::token`].
+    pub migration_port: Option<u16>,
+    pub broker: Option<String>,
+    /// Host the clients of the broker reach this server at, the address it connects to the
+    /// broker from if not given.
+    pub advertise: Option<String>,
+    /// Directory the Chrome traces of the sessions whose settings ask for tracing are written to.
+    pub trace_dir: PathBuf,
+    pub snapshot_dir: PathBuf,
+    /// Save a snapshot of every session each time it simulates this many frames.
+    pub snapshot_every: Option<u64>,
+    /// Snapshot the sessions start from instead of an empty world. The client has to run the
+    /// same scene as the session it was taken of, the bodies it uploads are matched to the
+    /// restored ones by their entities.
+    pub restore: Option<PathBuf>,
+    /// Level of the logs such as `"debug"`, `RUST_LOG` decides if not given.
+    #[serde(deserialize_with = "level")]
+    pub log_level: Option<LevelFilter>,
+    pub codec: CodecPolicy,
+    /// Clients have to present this token in their settings to start a session.
+    pub token: Option<String>,
+    /// Serve TLS instead of plain TCP, and QUIC with the same certificate. UDP and shared memory
+    /// are not served then.
+    pub tls: Option<TlsConfig>,
+}
+
+fn level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<LevelFilter>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|level| level.parse().map_err(|_| D::Error::custom(format!("unknown log level {level}"))))
+        .transpose()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 4001,
+            quic_port: None,
+            shm: None,
+            max_sessions: 1,
+            sessions: 1,
+            discovery: true,
+            migration_port: None,
+            broker: None,
+            advertise: None,
+            trace_dir: PathBuf::from("."),
+            snapshot_dir: PathBuf::from("."),
+            snapshot_every: None,
+            restore: None,
+            log_level: None,
+            codec: CodecPolicy::Client,
+            token: None,
+            tls: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("failed to open {}, {e}", path.display()))?;
+
+        ron::de::from_reader(file).map_err(|e| format!("invalid configuration {}, {e}", path.display()))
+    }
+}
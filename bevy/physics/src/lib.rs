@@ -0,0 +1,405 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy_ecs::prelude::Entity;
+use bevy_rapier3d::{
+    prelude::{RapierConfiguration, RapierContext, TimestepMode},
+    utils,
+};
+use log::{debug, error, warn};
+use tracing::info_span;
+use tracing_chrome::ChromeLayerBuilder;
+use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+use shared::{clock, codec::{self, CodecContext, CodecKind, NoneCodec}, frame::{micros, MessageKind}, handshake::{self, Handshake, Rejection}, settings::{Scene, Settings}};
+use shared::{migration::Moved, request::Request, response::{Response, SyncContext, Log}, state::SessionState, transport::Transport};
+
+use partition::Partitioned;
+
+mod config;
+mod migration;
+mod partition;
+mod snapshot;
+
+pub use config::{CodecPolicy, Config};
+pub use migration::Migrations;
+
+/// Runs a whole session over the transport, from the settings of the client until its shutdown
+/// or until it moves to another server.
+pub fn serve(mut transport: Box<dyn Transport>, config: &Config, migrations: &Migrations) {
+    let settings: Settings = match transport.recv(MessageKind::Settings, &mut NoneCodec) {
+        Ok((settings, _)) => settings,
+        Err(e) => {
+            error!("failed to receive settings, {e}");
+            return;
+        }
+    };
+
+    println!("{}", settings);
+    debug!("client connected over {}", transport.name());
+
+    let (requested, requested_context, checksum, generate_scene, token, partition, resume, lockstep) = match &settings.physics_plugin {
+        shared::settings::PhysicsPlugin::Server { codec, codec_context, checksum, generate_scene, token, partition, resume, lockstep, .. } => (*codec, *codec_context, *checksum, *generate_scene, token.as_deref(), partition.as_ref(), *resume, *lockstep),
+        shared::settings::PhysicsPlugin::Default => (CodecKind::None, CodecContext::Reset, false, false, None, None, None, None),
+    };
+
+    transport.set_checksum(checksum);
+
+    if let Err(rejection) = handshake::authenticate(config.token.as_deref(), token) {
+        send_rejection(transport.as_mut(), rejection);
+        return;
+    }
+
+    // A session moved from another server continues from the frame it reached there.
+    let resumed = match resume.map(|ticket| migrations.take(ticket)) {
+        Some(Some(state)) => Some(state),
+        Some(None) => {
+            send_rejection(transport.as_mut(), Rejection::UnknownSession);
+            return;
+        }
+        None => None,
+    };
+
+    // Otherwise the session starts from the snapshot the server restores, if any.
+    let restored = match (&resumed, &config.restore) {
+        (None, Some(path)) => match snapshot::load(path) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                error!("failed to restore {}, {e}", path.display());
+                return;
+            }
+        },
+        _ => None,
+    };
+
+    let (codec_kind, codec_context) = match config.codec {
+        CodecPolicy::Client => codec::negotiate(requested, requested_context),
+        CodecPolicy::Server { codec, codec_context } => codec::negotiate(codec, codec_context),
+    };
+    debug!("client requested {} codec with {} context, running {} with {}", requested, requested_context, codec_kind, codec_context);
+    if let Err(e) = transport.send(MessageKind::Handshake, &mut NoneCodec, &Handshake::Accepted { codec: codec_kind, codec_context }) {
+        error!("failed to send handshake, {e}");
+        return;
+    }
+    let mut codec = codec_kind.build(codec_context);
+
+    // The server emulates the link towards it, the plugin the one towards the client.
+    if let shared::settings::PhysicsPlugin::Server { network: Some(network), .. } = &settings.physics_plugin {
+        transport = shared::transport::emulate(transport, network.clone());
+    }
+
+    // Every session runs on its own thread and traces into its own file.
+    let _tracing = settings.tracing_level.as_ref().map(|_| {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros();
+        let (chrome_layer, guard) = ChromeLayerBuilder::new()
+            .file(config.trace_dir.join(format!("trace-{}-{}.json", settings, timestamp)))
+            .build();
+
+        (tracing::subscriber::set_default(tracing_subscriber::registry().with(chrome_layer)), guard)
+    });
+
+    let _span = info_span!("client_connected", name = "physics_server").entered();
+
+    debug!("accepted client");
+
+    let (resuming, restoring) = (resumed.is_some(), restored.is_some());
+    let (mut frame_count, mut context) = match resumed.or(restored) {
+        Some(state) => state.into_context(),
+        None => (0, RapierContext::default()),
+    };
+
+    // The client counts its frames from its own start, a restored session from the frame of the snapshot.
+    let base_frame = if restoring { frame_count } else { 0 };
+
+    let snapshot_every = config.snapshot_every.filter(|every| *every > 0);
+    let take_snapshot = |frame: u64, context: &mut RapierContext| {
+        let _span = info_span!("snapshot", name = "physics_server").entered();
+        let path = config.snapshot_dir.join(format!("snapshot-{}-{}.bin", settings, frame));
+
+        match snapshot::save(&path, frame, context) {
+            Ok(()) => debug!("saved the state of frame {} to {}", frame, path.display()),
+            Err(e) => error!("failed to save snapshot to {}, {e}", path.display()),
+        }
+    };
+
+    // Presented to the servers the session is handed over to.
+    let server_token = config.token.clone();
+    let config = RapierConfiguration::default();
+    let hooks_instance = ();
+
+    let mut partitioned = partition.map(|partition| {
+        let region = partition.region(partition.index);
+        debug!("simulating region {} of {}, from {} to {}", partition.index, partition.regions(), region.min, region.max);
+
+        Partitioned::new(partition)
+    });
+
+    // Handles of the generated scene, or of the restored one, are sent back with the first response.
+    let mut generated = if restoring {
+        snapshot::handles(&context)
+    } else if generate_scene && !resuming {
+        let _span = info_span!("generate_scene", name = "physics_server").entered();
+        let mut generated = generate(&settings.scene, &mut context);
+
+        if let Some(partitioned) = &partitioned {
+            partitioned.retain(&mut context, &mut generated);
+        }
+
+        generated
+    } else {
+        SyncContext::default()
+    };
+
+    loop {
+        let mut log = Log::default();
+
+        let req = {
+            let _span = info_span!("request_received", name = "physics_server").entered();
+            let (req, stats) = match transport.recv::<Request>(MessageKind::Request, codec.as_mut()) {
+                Ok(message) => message,
+                Err(e) => {
+                    error!("failed to receive request, {e}");
+                    return;
+                }
+            };
+            log.decompress_time = stats.elapsed;
+            log.received_at = clock::now() - stats.elapsed as u64;
+
+            req
+        };
+
+        let steps = match req {
+            Request::Shutdown => {
+                log::debug!("shutdown is received");
+                return;
+            },
+            Request::Snapshot => {
+                take_snapshot(frame_count, &mut context);
+                continue;
+            }
+            Request::Migrate { endpoint } => {
+                let _span = info_span!("migrate", name = "physics_server").entered();
+
+                let handover = migration::handover(SessionState::new(frame_count, std::mem::take(&mut context)), server_token.clone());
+
+                let moved = match migration::hand_over(&endpoint, &handover) {
+                    Ok(()) => Moved::Ready { ticket: handover.ticket },
+                    Err(rejection) => {
+                        warn!("session stays on this server, {rejection}");
+                        (frame_count, context) = handover.state.into_context();
+                        Moved::Failed(rejection)
+                    }
+                };
+
+                if let Err(e) = transport.send(MessageKind::Migration, &mut NoneCodec, &moved) {
+                    error!("failed to send migration result, {e}");
+                    return;
+                }
+
+                if let Moved::Ready { .. } = moved {
+                    debug!("session at frame {} is handed over to {}", frame_count, endpoint);
+                    return;
+                }
+
+                continue;
+            }
+            Request::SyncContext(sync_context) => {
+                let (mut response, steps) = {
+                    let _span = info_span!("processing", name = "physics_server").entered();
+
+                    let instant = std::time::Instant::now();
+
+                    let mut response = std::mem::take(&mut generated);
+
+                    let phase = std::time::Instant::now();
+
+                    // A client uploads the scene a restored session already holds, its handles went
+                    // out with the first response.
+                    for rb in sync_context.rigid_bodies {
+                        let entity = Entity::from_bits(rb.user_data as u64);
+                        if context.entity2body.contains_key(&entity) {
+                            continue;
+                        }
+
+                        let handle = context.bodies.insert(rb);
+
+                        context.entity2body.insert(entity, handle);
+                        //context.last_body_transform_set.insert(handle, bevy_transform::prelude::GlobalTransform::IDENTITY);
+                        response.rigid_body_handles.push((entity.to_bits(), handle));
+                    }
+
+                    for collider in sync_context.colliders {
+                        let entity = Entity::from_bits(collider.user_data as u64);
+                        if context.entity2collider.contains_key(&entity) {
+                            continue;
+                        }
+
+                        let handle = if let Some(body_handle) = context.entity2body.get(&entity) {
+                            context.colliders.insert_with_parent(
+                                collider,
+                                *body_handle,
+                                &mut context.bodies,
+                            )
+                        } else {
+                            context.colliders.insert(collider)
+                        };
+
+                        context.entity2collider.insert(entity, handle);
+                        response.collider_handles.push((entity.to_bits(), handle));
+                    }
+
+                    log.phases.insert = micros(phase.elapsed());
+
+                    if let Some(partitioned) = &mut partitioned {
+                        partitioned.receive(&mut context, sync_context.handoffs, sync_context.ghosts, &mut response);
+                    }
+
+                    // In lockstep the world catches up with the client in fixed steps, none while the
+                    // client is still within the current one.
+                    let (steps, timestep_mode, delta_seconds) = match (lockstep, sync_context.frame) {
+                        (Some(lockstep), Some(frame)) => (
+                            (base_frame + frame).saturating_sub(frame_count).min(lockstep.max_substeps as u64),
+                            TimestepMode::Fixed { dt: lockstep.timestep, substeps: 1 },
+                            lockstep.timestep,
+                        ),
+                        _ => (1, config.timestep_mode, sync_context.delta_seconds),
+                    };
+
+                    for _ in 0..steps {
+                        context.step_simulation(
+                            config.gravity,
+                            timestep_mode,
+                            None,
+                            &hooks_instance,
+                            delta_seconds,
+                            &mut bevy_rapier3d::prelude::SimulationToRenderTime { diff: 0.0 },
+                            None,
+                        );
+
+                        // The counters start over with every step.
+                        let counters = &context.pipeline.counters;
+                        let from_millis = |millis: f64| (millis * 1000.0) as u32;
+                        log.phases.broad_phase += from_millis(counters.broad_phase_time());
+                        log.phases.narrow_phase += from_millis(counters.narrow_phase_time());
+                        log.phases.island_construction += from_millis(counters.island_construction_time());
+                        log.phases.solver += from_millis(counters.solver_time());
+                        log.phases.ccd += from_millis(counters.ccd_time());
+                    }
+                    response.frame = frame_count + steps;
+
+                    let phase = std::time::Instant::now();
+
+                    for (_, rb) in context.bodies.iter() {
+                        // Nothing moved if the world did not step.
+                        if steps == 0 || partitioned.as_ref().is_some_and(|partitioned| !partitioned.reports(rb)) {
+                            continue;
+                        }
+
+                        let interpolated_pos =
+                            utils::iso_to_transform(rb.position(), context.physics_scale());
+                        response
+                            .transforms
+                            .push((rb.user_data as u64, interpolated_pos));
+                    }
+
+                    if let Some(partitioned) = &partitioned {
+                        partitioned.emit(&mut context, &mut response);
+                    }
+
+                    log.phases.collect = micros(phase.elapsed());
+
+                    log.physics_time = micros(instant.elapsed());
+
+                    (response, steps)
+                };
+
+                {
+                    let _span = info_span!("responded", name = "physics_server").entered();
+
+                    // Over UDP the transforms leave as an unreliable snapshot, only the handles need to arrive.
+                    let snapshot = if transport.supports_snapshots() {
+                        Some(std::mem::take(&mut response.transforms))
+                    } else {
+                        None
+                    };
+
+                    let stats = match transport.send(MessageKind::Response, codec.as_mut(), &Response::SyncContext(response)) {
+                        Ok(stats) => stats,
+                        Err(e) => {
+                            error!("failed to send response, {e}");
+                            return;
+                        }
+                    };
+                    log.compress_time = stats.elapsed;
+                    log.responded_at = clock::now();
+
+                    if let Some(transforms) = snapshot {
+                        if let Err(e) = transport.send_snapshot(&shared::response::encode_snapshot(&transforms)) {
+                            error!("failed to send snapshot, {e}");
+                            return;
+                        }
+                    }
+
+                    if let Err(e) = transport.send(MessageKind::Log, &mut NoneCodec, &log) {
+                        error!("failed to send log, {e}");
+                        return;
+                    }
+                }
+
+                steps
+            }
+        };
+
+        let previous = frame_count;
+        frame_count += steps;
+        log::debug!("frame {}", frame_count);
+
+        // Several steps at once may pass over the frame a snapshot is due at.
+        if snapshot_every.is_some_and(|every| frame_count / every > previous / every) {
+            take_snapshot(frame_count, &mut context);
+        }
+    }
+}
+
+/// Reads the settings of a client only to tell it why it cannot start a session.
+pub fn reject(mut transport: Box<dyn Transport>, rejection: Rejection) {
+    if let Err(e) = transport.recv::<Settings>(MessageKind::Settings, &mut NoneCodec) {
+        error!("failed to receive settings, {e}");
+        return;
+    }
+
+    send_rejection(transport.as_mut(), rejection);
+}
+
+fn send_rejection(transport: &mut dyn Transport, rejection: Rejection) {
+    warn!("rejecting client connected over {}, {rejection}", transport.name());
+
+    if let Err(e) = transport.send(MessageKind::Handshake, &mut NoneCodec, &Handshake::Rejected(rejection)) {
+        error!("failed to send rejection, {e}");
+    }
+
+    let _ = transport.close();
+}
+
+fn generate(scene: &Scene, context: &mut RapierContext) -> SyncContext {
+    let mut response = SyncContext::default();
+
+    for (index, body) in shared::scene::bodies(scene).into_iter().enumerate() {
+        // The client does not upload its entities in this mode, so the scene index stands in for the entity.
+        let entity = Entity::from_bits(index as u64);
+
+        let body_handle = context.bodies.insert(body.rigid_body(index as u128));
+        let collider_handle = context.colliders.insert_with_parent(
+            body.collider(index as u128),
+            body_handle,
+            &mut context.bodies,
+        );
+
+        context.entity2body.insert(entity, body_handle);
+        context.entity2collider.insert(entity, collider_handle);
+        response.rigid_body_handles.push((entity.to_bits(), body_handle));
+        response.collider_handles.push((entity.to_bits(), collider_handle));
+    }
+
+    debug!("generated scene with {} bodies", response.rigid_body_handles.len());
+
+    response
+}
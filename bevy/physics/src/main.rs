@@ -1,162 +1,266 @@
-use std::{net::SocketAddrV4, io::{Write, BufWriter, BufReader}};
-
-use bevy_ecs::prelude::Entity;
-use bevy_rapier3d::{
-    prelude::{RapierConfiguration, RapierContext},
-    utils,
-};
-use log::debug;
-use tracing::info_span;
-use tracing_chrome::ChromeLayerBuilder;
-use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt};
-use shared::{deflate::{Compressor, CONFIG, Decompressor}, settings::Settings};
-use shared::{request::Request, response::{Response, SyncContext, Log}};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use clap::Parser;
+use log::{debug, error, LevelFilter};
+use physics::{CodecPolicy, Config, Migrations};
+use serde::de::DeserializeOwned;
+use shared::codec::{CodecContext, CodecKind};
+use shared::discovery::{self, Announcement, Service};
+use shared::handshake::Rejection;
+use shared::registry;
+use shared::transport::Listener;
+
+const DEFAULT_CONFIG: &str = "Server.ron";
+
+/// Physics server for bevy-edge clients. Options given here override the configuration file.
+#[derive(Parser)]
+struct Args {
+    /// Configuration file in RON, Server.ron in the working directory if it exists.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    #[arg(long)]
+    bind: Option<IpAddr>,
+    /// Port of TCP or TLS and UDP.
+    #[arg(long)]
+    port: Option<u16>,
+    /// Port of QUIC, the one after --port if not given.
+    #[arg(long)]
+    quic_port: Option<u16>,
+    /// File shared with clients on the same host, it must not exist.
+    #[arg(long)]
+    shm: Option<PathBuf>,
+    /// Sessions served at the same time, further clients are rejected until one finishes.
+    #[arg(long)]
+    max_sessions: Option<usize>,
+    /// Sessions to serve before exiting, 0 to keep serving.
+    #[arg(long)]
+    sessions: Option<usize>,
+    #[arg(long)]
+    no_discovery: bool,
+    /// Port the sessions handed over by other servers arrive on, such as 4200. Handovers are not
+    /// accepted if not given, and need the token of the configuration.
+    #[arg(long)]
+    migration_port: Option<u16>,
+    /// Broker to register with, such as 10.0.0.1:4100.
+    #[arg(long)]
+    broker: Option<String>,
+    /// Host the clients of the broker reach this server at.
+    #[arg(long)]
+    advertise: Option<String>,
+    #[arg(long)]
+    trace_dir: Option<PathBuf>,
+    #[arg(long)]
+    snapshot_dir: Option<PathBuf>,
+    /// Save a snapshot of every session each time it simulates this many frames.
+    #[arg(long)]
+    snapshot_every: Option<u64>,
+    /// Snapshot the sessions start from instead of an empty world.
+    #[arg(long)]
+    restore: Option<PathBuf>,
+    /// Level of the logs, overrides RUST_LOG.
+    #[arg(long)]
+    log_level: Option<LevelFilter>,
+    /// Codec to run whatever the clients ask for, in RON such as `Zstd(3)`.
+    #[arg(long, value_parser = parse_ron::<CodecKind>)]
+    codec: Option<CodecKind>,
+    /// Context of the codec given with --codec, in RON such as `Stream`.
+    #[arg(long, requires = "codec", value_parser = parse_ron::<CodecContext>)]
+    codec_context: Option<CodecContext>,
+}
 
-fn main() {
-    env_logger::init();
+fn parse_ron<T: DeserializeOwned>(value: &str) -> Result<T, ron::error::SpannedError> {
+    ron::from_str(value)
+}
 
-    debug!("starting physics server");
+impl Args {
+    fn config(self) -> Result<Config, String> {
+        let mut config = match self.config {
+            Some(path) => Config::load(&path)?,
+            None if Path::new(DEFAULT_CONFIG).exists() => Config::load(Path::new(DEFAULT_CONFIG))?,
+            None => Config::default(),
+        };
 
-    let srv = std::net::TcpListener::bind("0.0.0.0:4001".parse::<SocketAddrV4>().unwrap()).unwrap();
+        if let Some(bind) = self.bind {
+            config.bind = bind;
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(quic_port) = self.quic_port {
+            config.quic_port = Some(quic_port);
+        }
+        if let Some(shm) = self.shm {
+            config.shm = Some(shm);
+        }
+        if let Some(max_sessions) = self.max_sessions {
+            config.max_sessions = max_sessions;
+        }
+        if let Some(sessions) = self.sessions {
+            config.sessions = sessions;
+        }
+        if self.no_discovery {
+            config.discovery = false;
+        }
+        if let Some(migration_port) = self.migration_port {
+            config.migration_port = Some(migration_port);
+        }
+        if let Some(broker) = self.broker {
+            config.broker = Some(broker);
+        }
+        if let Some(advertise) = self.advertise {
+            config.advertise = Some(advertise);
+        }
+        if let Some(trace_dir) = self.trace_dir {
+            config.trace_dir = trace_dir;
+        }
+        if let Some(snapshot_dir) = self.snapshot_dir {
+            config.snapshot_dir = snapshot_dir;
+        }
+        if let Some(snapshot_every) = self.snapshot_every {
+            config.snapshot_every = Some(snapshot_every);
+        }
+        if let Some(restore) = self.restore {
+            config.restore = Some(restore);
+        }
+        if let Some(log_level) = self.log_level {
+            config.log_level = Some(log_level);
+        }
+        if let Some(codec) = self.codec {
+            config.codec = CodecPolicy::Server { codec, codec_context: self.codec_context.unwrap_or_default() };
+        }
+
+        Ok(config)
+    }
+}
 
-    run_physics_server(&srv);
+/// Frees the slot of a session however the session ends.
+struct Slot(Arc<AtomicUsize>);
 
-    debug!("Server is finished, terminating");
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
 }
 
-fn run_physics_server(srv: &std::net::TcpListener) {
-    let (tcp_stream, _) = srv
-        .accept()
-        .unwrap();
+fn main() {
+    let config = match Args::parse().config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    shared::init_logger(config.log_level);
+
+    debug!("starting physics server");
+
+    let address = SocketAddr::new(config.bind, config.port).to_string();
+    let quic_port = config.quic_port.unwrap_or_else(|| config.port.checked_add(1).expect("there is no port after --port for quic, give --quic-port"));
+    let quic_address = SocketAddr::new(config.bind, quic_port).to_string();
+
+    let srv = Listener::new();
+    match &config.tls {
+        // UDP and shared memory carry the sessions in the clear, they would get around TLS.
+        Some(tls) => {
+            srv.tls(&address, tls).unwrap();
+            debug!("tls is configured, udp and shm are not served");
+        }
+        None => {
+            srv.tcp(&address).unwrap();
+            srv.udp(&address).unwrap();
+            srv.shm(&config.shm.clone().unwrap_or_else(|| shared::transport::default_shm_path(config.port))).unwrap();
+        }
+    }
+    srv.quic(&quic_address, config.tls.as_ref()).unwrap();
+
+    debug!("listening on {address}, quic on {quic_address}");
 
-    let settings: Settings = bincode::serde::decode_from_std_read(&mut &tcp_stream, CONFIG).unwrap();
+    let config = Arc::new(config);
+    let active = Arc::new(AtomicUsize::new(0));
 
-    println!("{}", settings);
+    let announce = {
+        let config = config.clone();
+        let active = active.clone();
 
-    let compress = match settings.physics_plugin {
-        shared::settings::PhysicsPlugin::Server { compress, .. } => compress,
-        shared::settings::PhysicsPlugin::Default => None,
+        move || Announcement {
+            service: Service::Physics,
+            port: config.port,
+            quic_port: Some(quic_port),
+            tls: config.tls.is_some(),
+            token_required: config.token.is_some(),
+            active_sessions: active.load(Ordering::Acquire) as u32,
+            max_sessions: config.max_sessions as u32,
+        }
     };
 
-    if let Some(_) = settings.tracing_level {
-        let (chrome_layer, _guard) = ChromeLayerBuilder::new().build();
-        tracing_subscriber::registry().with(chrome_layer).init();
+    if config.discovery {
+        discovery::respond(Service::Physics, announce.clone()).unwrap();
     }
 
-    let _span = info_span!("client_connected", name = "physics_server").entered();
+    if let Some(broker) = &config.broker {
+        registry::register(broker.clone(), config.advertise.clone(), config.token.clone(), announce);
+    }
 
-    debug!("accepted client");
+    // Without the port, clients cannot resume sessions here.
+    let migrations = match config.migration_port {
+        Some(port) => {
+            // Anyone reaching the port could otherwise plant sessions on the server.
+            let token = config.token.clone().expect("accepting handovers needs a token in the configuration, which the other servers share");
+            let migration_address = SocketAddr::new(config.bind, port).to_string();
+            let config = config.clone();
+            let active = active.clone();
 
-    let mut context = RapierContext::default();
-    let config = RapierConfiguration::default();
-    let hooks_instance = ();
-    let mut frame_count = 0;
+            let accepts = move |pending| active.load(Ordering::Acquire) + pending < config.max_sessions;
+            let migrations = Migrations::bind(&migration_address, token, accepts).unwrap();
+            debug!("accepting handovers on {migration_address}");
 
-    loop {
-        let mut log = Log::default();
+            migrations
+        }
+        None => Migrations::default(),
+    };
 
-        let req = if compress.is_some() {
-            let _span = info_span!("request_received", name = "physics_server").entered();
-            let mut decompressor = BufReader::new(Decompressor::new(&tcp_stream));
-            let req = bincode::serde::decode_from_std_read::<Request, _, _>(&mut decompressor, CONFIG).unwrap();
-            let mut decompressor = decompressor.into_inner();
-            decompressor.finish().unwrap();
-            log.decompress_time = decompressor.elapsed();
+    println!("{}", shared::SERVER_READY);
 
-            req
-        } else {
-            let _span = info_span!("request_received", name = "physics_server").entered();
-            bincode::serde::decode_from_std_read::<Request, _, _>(&mut BufReader::new(&tcp_stream), CONFIG).unwrap()
-        };
+    let mut sessions: Vec<JoinHandle<()>> = Vec::new();
+    let mut served = 0;
 
-        match req {
-            Request::Shutdown => {
-                log::debug!("shutdown is received");
-                return;
-            },
-            Request::SyncContext(sync_context) => {
-                let response = {
-                    let _span = info_span!("processing", name = "physics_server").entered();
-
-                    let instant = std::time::Instant::now();
-
-                    let mut response = SyncContext::default();
-
-                    for rb in sync_context.rigid_bodies {
-                        let entity = Entity::from_bits(rb.user_data as u64);
-                        let handle = context.bodies.insert(rb);
-
-                        context.entity2body.insert(entity, handle);
-                        //context.last_body_transform_set.insert(handle, bevy_transform::prelude::GlobalTransform::IDENTITY);
-                        response.rigid_body_handles.push((entity.to_bits(), handle));
-                    }
-
-                    for collider in sync_context.colliders {
-                        let entity = Entity::from_bits(collider.user_data as u64);
-                        let handle = if let Some(body_handle) = context.entity2body.get(&entity) {
-                            context.colliders.insert_with_parent(
-                                collider,
-                                *body_handle,
-                                &mut context.bodies,
-                            )
-                        } else {
-                            context.colliders.insert(collider)
-                        };
-
-                        context.entity2collider.insert(entity, handle);
-                        response.collider_handles.push((entity.to_bits(), handle));
-                    }
-
-                    context.step_simulation(
-                        config.gravity,
-                        config.timestep_mode,
-                        None,
-                        &hooks_instance,
-                        sync_context.delta_seconds,
-                        &mut bevy_rapier3d::prelude::SimulationToRenderTime { diff: 0.0 },
-                        None,
-                    );
-
-                    for (_, rb) in context.bodies.iter() {
-                        let interpolated_pos =
-                            utils::iso_to_transform(rb.position(), context.physics_scale());
-                        response
-                            .transforms
-                            .push((rb.user_data as u64, interpolated_pos));
-                    }
-
-                    log.physics_time = instant.elapsed().as_micros().try_into().unwrap();
-
-                    response
-                };
-
-                {
-                    let _span = info_span!("responded", name = "physics_server").entered();
-
-                    if let Some(level) = compress {
-                        let mut compressor = BufWriter::new(Compressor::new(&tcp_stream, level));
-                        bincode::serde::encode_into_std_write(&Response::SyncContext(response), &mut compressor, CONFIG)
-                            .unwrap();
-                        compressor.flush().unwrap();
-                        let compressor = compressor.into_inner().map_err(|_| "failed to get inner of compressor buffer writer").unwrap();
-                        log.compress_time = compressor.elapsed();
-                    } else {
-                        bincode::serde::encode_into_std_write(&Response::SyncContext(response), &mut BufWriter::new(&tcp_stream), CONFIG)
-                            .unwrap();
-                    }
-
-                    {
-                        bincode::serde::encode_into_std_write(&log, &mut BufWriter::with_capacity(1024, &tcp_stream), CONFIG).unwrap();
-                    }
-
-                    // Force flushing the buffer
-                    tcp_stream.set_nodelay(true).unwrap();
-                    tcp_stream.set_nodelay(false).unwrap();
-                }
+    while config.sessions == 0 || served < config.sessions {
+        let transport = match srv.accept() {
+            Ok(transport) => transport,
+            Err(e) => {
+                error!("failed to accept a client, {e}");
+                continue;
             }
+        };
+
+        if active.load(Ordering::Acquire) >= config.max_sessions {
+            std::thread::spawn(move || physics::reject(transport, Rejection::Busy));
+            continue;
         }
 
-        frame_count += 1;
-        log::debug!("frame {}", frame_count);
+        active.fetch_add(1, Ordering::AcqRel);
+        let slot = Slot(active.clone());
+        served += 1;
+        sessions.retain(|session| !session.is_finished());
+
+        let config = config.clone();
+        let migrations = migrations.clone();
+        sessions.push(std::thread::spawn(move || {
+            let _slot = slot;
+            physics::serve(transport, &config, &migrations);
+        }));
+    }
+
+    for session in sessions {
+        if session.join().is_err() {
+            error!("a session ended with a panic");
+        }
     }
+
+    debug!("Server is finished, terminating");
 }
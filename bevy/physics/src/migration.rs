@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use log::{debug, error, warn};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use shared::codec::{CodecContext, NoneCodec};
+use shared::frame::{FrameError, MessageKind};
+use shared::handshake::{self, Rejection};
+use shared::migration::{self, Handover, Receipt};
+use shared::state::SessionState;
+use shared::transport::{self, Transport, TransportKind};
+
+/// How long a session handed over waits for its client before it is dropped.
+const EXPIRY: Duration = Duration::from_secs(10);
+/// How long the source server may stay silent while it hands a session over.
+const HANDOVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Pending = Mutex<HashMap<u64, (Instant, SessionState)>>;
+
+/// Sessions handed over by other servers, waiting for their clients to resume them.
+#[derive(Clone, Default)]
+pub struct Migrations {
+    pending: Arc<Pending>,
+}
+
+impl Migrations {
+    /// Accepts handovers from the servers presenting the token on the address in the background,
+    /// one after the other, as long as `accepts` allows another session besides the ones waiting
+    /// for their clients.
+    pub fn bind<F>(address: &str, token: String, accepts: F) -> io::Result<Self>
+    where
+        F: Fn(usize) -> bool + Send + 'static,
+    {
+        let listener = TcpListener::bind(address)?;
+        let migrations = Migrations::default();
+
+        let receiving = migrations.clone();
+        std::thread::spawn(move || loop {
+            let transport = listener.accept().and_then(|(stream, _)| {
+                stream.set_read_timeout(Some(HANDOVER_TIMEOUT))?;
+                stream.set_write_timeout(Some(HANDOVER_TIMEOUT))?;
+                transport::tcp(stream)
+            });
+
+            match transport {
+                Ok(transport) => receiving.receive(transport, &token, &accepts),
+                Err(e) => error!("failed to accept a handover, {e}"),
+            }
+        });
+
+        let expiring = Arc::downgrade(&migrations.pending);
+        std::thread::spawn(move || expire(expiring));
+
+        Ok(migrations)
+    }
+
+    fn receive<F: Fn(usize) -> bool>(&self, mut transport: Box<dyn Transport>, token: &str, accepts: F) {
+        let mut codec = migration::CODEC.build(CodecContext::Reset);
+
+        let handover: Handover = match transport.recv(MessageKind::Migration, codec.as_mut()) {
+            Ok((handover, _)) => handover,
+            Err(e) => {
+                error!("failed to receive handover, {e}");
+                return;
+            }
+        };
+
+        let receipt: Receipt = handshake::authenticate(Some(token), handover.token.as_deref()).and_then(|()| {
+            let mut pending = self.pending.lock().unwrap();
+
+            if !accepts(pending.len()) {
+                return Err(Rejection::Busy);
+            }
+
+            debug!("session at frame {} is handed over", handover.state.frame);
+            pending.insert(handover.ticket, (Instant::now(), handover.state));
+
+            Ok(())
+        });
+
+        if let Err(rejection) = &receipt {
+            warn!("refusing handover, {rejection}");
+        }
+
+        if let Err(e) = transport.send(MessageKind::Migration, &mut NoneCodec, &receipt) {
+            error!("failed to answer handover, {e}");
+        }
+
+        let _ = transport.close();
+    }
+
+    /// State of the session handed over with the ticket, if it did not expire.
+    pub fn take(&self, ticket: u64) -> Option<SessionState> {
+        self.pending.lock().unwrap()
+            .remove(&ticket)
+            .filter(|(received, _)| received.elapsed() < EXPIRY)
+            .map(|(_, state)| state)
+    }
+}
+
+/// Drops the sessions whose clients did not come for them, until the migrations are gone.
+fn expire(pending: Weak<Pending>) {
+    loop {
+        std::thread::sleep(EXPIRY / 4);
+
+        let Some(pending) = pending.upgrade() else {
+            break;
+        };
+
+        pending.lock().unwrap().retain(|ticket, (received, _)| {
+            let expired = received.elapsed() >= EXPIRY;
+            if expired {
+                debug!("session handed over with ticket {ticket} expired");
+            }
+
+            !expired
+        });
+    }
+}
+
+/// Session on its way to another server, under a ticket nobody can guess.
+pub fn handover(state: SessionState, token: Option<String>) -> Handover {
+    Handover { token, ticket: OsRng.next_u64(), state }
+}
+
+/// Hands the session over to the server accepting handovers at the endpoint. The source keeps
+/// the session if the target does not take it.
+pub fn hand_over(endpoint: &str, handover: &Handover) -> Result<(), Rejection> {
+    match send(endpoint, handover) {
+        Ok(receipt) => receipt,
+        Err(e) => {
+            warn!("failed to hand the session over to {endpoint}, {e}");
+            Err(Rejection::Unreachable)
+        }
+    }
+}
+
+fn send(endpoint: &str, handover: &Handover) -> Result<Receipt, FrameError> {
+    let mut transport = transport::connect(&TransportKind::Tcp, endpoint)?;
+    let mut codec = migration::CODEC.build(CodecContext::Reset);
+
+    transport.send(MessageKind::Migration, codec.as_mut(), handover)?;
+    let (receipt, _) = transport.recv(MessageKind::Migration, &mut NoneCodec)?;
+    let _ = transport.close();
+
+    Ok(receipt)
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+
+use bevy_ecs::prelude::Entity;
+use bevy_rapier3d::{
+    prelude::RapierContext,
+    rapier::prelude::{RigidBody, RigidBodyHandle, RigidBodyType},
+};
+use shared::partition::{Partition, Region, Transfer};
+use shared::response::SyncContext;
+
+/// State of a server that simulates one region of a partitioned world.
+pub(crate) struct Partitioned {
+    region: Region,
+    /// Bodies that do not move are in every region, only the first one reports them.
+    reports_static: bool,
+    /// Bodies mirrored from the neighbours, by entity. They follow their owner and are never reported.
+    ghosts: HashMap<u64, RigidBodyHandle>,
+}
+
+impl Partitioned {
+    pub fn new(partition: &Partition) -> Self {
+        Partitioned {
+            region: partition.region(partition.index),
+            reports_static: partition.index == 0,
+            ghosts: HashMap::new(),
+        }
+    }
+
+    /// Whether the transform of the body is sent to the client.
+    pub fn reports(&self, rb: &RigidBody) -> bool {
+        rb.is_dynamic() || (self.reports_static && !self.ghosts.contains_key(&(rb.user_data as u64)))
+    }
+
+    /// Drops the generated bodies outside of the region, the servers of the other regions generate them too.
+    pub fn retain(&self, context: &mut RapierContext, generated: &mut SyncContext) {
+        let outside: Vec<RigidBodyHandle> = context.bodies.iter()
+            .filter(|(_, rb)| rb.is_dynamic() && !self.region.contains(rb))
+            .map(|(handle, _)| handle)
+            .collect();
+
+        for handle in outside {
+            remove(context, handle);
+        }
+
+        generated.rigid_body_handles.retain(|(_, handle)| context.bodies.contains(*handle));
+        generated.collider_handles.retain(|(_, handle)| context.colliders.contains(*handle));
+    }
+
+    /// Takes over the bodies handed off by the neighbours and moves the ghosts to where their owners put them.
+    pub fn receive(&mut self, context: &mut RapierContext, handoffs: Vec<Transfer>, ghosts: Vec<Transfer>, response: &mut SyncContext) {
+        for transfer in handoffs {
+            let entity = transfer.body.user_data as u64;
+
+            if let Some(ghost) = self.ghosts.remove(&entity) {
+                remove(context, ghost);
+            }
+
+            let handle = insert(context, transfer);
+            context.entity2body.insert(Entity::from_bits(entity), handle);
+            response.rigid_body_handles.push((entity, handle));
+
+            for collider in context.bodies[handle].colliders() {
+                let collider_entity = context.colliders[*collider].user_data as u64;
+
+                context.entity2collider.insert(Entity::from_bits(collider_entity), *collider);
+                response.collider_handles.push((collider_entity, *collider));
+            }
+        }
+
+        let mut stale = std::mem::take(&mut self.ghosts);
+
+        for ghost in ghosts {
+            let entity = ghost.body.user_data as u64;
+
+            // The owner may not know yet that the body is handed off to this region.
+            if context.entity2body.contains_key(&Entity::from_bits(entity)) {
+                continue;
+            }
+
+            let handle = match stale.remove(&entity) {
+                Some(handle) => {
+                    context.bodies[handle].set_next_kinematic_position(*ghost.body.position());
+                    handle
+                }
+                None => {
+                    let mut ghost = ghost;
+                    ghost.body.set_body_type(RigidBodyType::KinematicPositionBased, true);
+                    insert(context, ghost)
+                }
+            };
+
+            self.ghosts.insert(entity, handle);
+        }
+
+        for handle in stale.into_values() {
+            remove(context, handle);
+        }
+    }
+
+    /// Hands off the bodies that left the region and mirrors the ones close to its edges. Runs
+    /// after the transforms are reported, so the bodies leaving still show up in this frame.
+    pub fn emit(&self, context: &mut RapierContext, response: &mut SyncContext) {
+        let mut leaving = Vec::new();
+
+        // Ghosts are kinematic and static bodies stay where they are.
+        for (handle, rb) in context.bodies.iter().filter(|(_, rb)| rb.is_dynamic()) {
+            if !self.region.contains(rb) {
+                leaving.push(handle);
+            } else if self.region.near_edge(rb) {
+                response.ghosts.push(transfer(context, handle));
+            }
+        }
+
+        for handle in leaving {
+            response.handoffs.push(transfer(context, handle));
+            remove(context, handle);
+        }
+    }
+}
+
+fn transfer(context: &RapierContext, handle: RigidBodyHandle) -> Transfer {
+    let body = context.bodies[handle].clone();
+    let colliders = body.colliders().iter().map(|collider| context.colliders[*collider].clone()).collect();
+
+    Transfer { body, colliders }
+}
+
+fn insert(context: &mut RapierContext, transfer: Transfer) -> RigidBodyHandle {
+    let handle = context.bodies.insert(transfer.body);
+
+    for collider in transfer.colliders {
+        context.colliders.insert_with_parent(collider, handle, &mut context.bodies);
+    }
+
+    handle
+}
+
+fn remove(context: &mut RapierContext, handle: RigidBodyHandle) {
+    let entity = Entity::from_bits(context.bodies[handle].user_data as u64);
+
+    for collider in context.bodies[handle].colliders() {
+        let collider_entity = Entity::from_bits(context.colliders[*collider].user_data as u64);
+
+        if context.entity2collider.get(&collider_entity) == Some(collider) {
+            context.entity2collider.remove(&collider_entity);
+        }
+    }
+
+    if context.entity2body.get(&entity) == Some(&handle) {
+        context.entity2body.remove(&entity);
+    }
+
+    context.bodies.remove(
+        handle,
+        &mut context.islands,
+        &mut context.colliders,
+        &mut context.impulse_joints,
+        &mut context.multibody_joints,
+        true,
+    );
+}
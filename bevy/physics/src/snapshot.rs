@@ -0,0 +1,40 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Write};
+use std::path::Path;
+
+use bevy_rapier3d::prelude::RapierContext;
+use shared::response::SyncContext;
+use shared::state::SessionState;
+use shared::CONFIG;
+
+/// Writes the state of the session to a file that sessions can start from, see [`crate::Config::restore`].
+pub(crate) fn save(path: &Path, frame: u64, context: &mut RapierContext) -> io::Result<()> {
+    let state = SessionState::new(frame, std::mem::take(context));
+    let written = write(path, &state);
+    (_, *context) = state.into_context();
+
+    written
+}
+
+fn write(path: &Path, state: &SessionState) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    bincode::serde::encode_into_std_write(state, &mut file, CONFIG).map_err(io::Error::other)?;
+
+    file.flush()
+}
+
+pub(crate) fn load(path: &Path) -> io::Result<SessionState> {
+    let mut file = BufReader::new(File::open(path)?);
+
+    bincode::serde::decode_from_std_read(&mut file, CONFIG).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Handles of everything in a restored context, sent with the first response like the ones of a
+/// generated scene.
+pub(crate) fn handles(context: &RapierContext) -> SyncContext {
+    SyncContext {
+        rigid_body_handles: context.entity2body.iter().map(|(entity, handle)| (entity.to_bits(), *handle)).collect(),
+        collider_handles: context.entity2collider.iter().map(|(entity, handle)| (entity.to_bits(), *handle)).collect(),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,361 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use log::{info, warn, LevelFilter};
+use serde::Serialize;
+use shared::matrix::Matrix;
+use shared::settings::{PhysicsPlugin, Settings};
+use shared::transport::TransportKind;
+use shared::SERVER_READY;
+
+/// How long the physics server may take to bind its listeners.
+const SERVER_START: Duration = Duration::from_secs(10);
+
+/// How long the physics server may take to exit once its session ended.
+const SERVER_STOP: Duration = Duration::from_secs(5);
+
+/// Runs every case of a benchmark matrix against a physics server on this host, one after the
+/// other, and gathers the outputs of the runs into the results directory.
+#[derive(Parser)]
+struct Args {
+    matrix: PathBuf,
+    /// Directory the runs are written to, every run in a directory of its own.
+    #[arg(long, default_value = "results")]
+    results: PathBuf,
+    /// The bevy-edge client, the one next to the runner if not given.
+    #[arg(long)]
+    client: Option<PathBuf>,
+    /// The physics server, the one next to the runner if not given.
+    #[arg(long)]
+    server: Option<PathBuf>,
+    /// Configuration file of the physics server, such as one with TLS for the cases that need it.
+    #[arg(long)]
+    server_config: Option<PathBuf>,
+    /// Port of the physics server on 127.0.0.1, QUIC listens on the next one.
+    #[arg(long, default_value_t = 4001)]
+    port: u16,
+    #[arg(long, default_value_t = 1)]
+    repetitions: u32,
+    /// Times a run that fails is started again.
+    #[arg(long, default_value_t = 2)]
+    retries: u32,
+    /// Seconds a run may take beyond the bench length of its case before it is stopped.
+    #[arg(long, default_value_t = 60)]
+    timeout: u64,
+    /// Format of the rows the client writes, csv, jsonl or columnar.
+    #[arg(long, default_value = "csv")]
+    format: String,
+    /// Level of the logs, overrides RUST_LOG.
+    #[arg(long)]
+    log_level: Option<LevelFilter>,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+enum Outcome {
+    Succeeded,
+    /// The client exited with the code, none if it was killed by a signal.
+    Failed(Option<i32>),
+    TimedOut,
+    ServerUnavailable,
+    /// The case needs more than the one server the runner starts, it did not run.
+    Skipped,
+}
+
+#[derive(Serialize)]
+struct Run {
+    name: String,
+    settings: String,
+    case: usize,
+    repetition: u32,
+    attempts: u32,
+    outcome: Outcome,
+    /// Seconds from the first attempt until the last one finished.
+    duration: f32,
+}
+
+/// Written to the results after every run, so that an interrupted matrix still lists what ran.
+#[derive(Serialize)]
+struct Manifest {
+    matrix: PathBuf,
+    client: PathBuf,
+    server: PathBuf,
+    runs: Vec<Run>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    shared::init_logger(args.log_level);
+
+    let matrix: Matrix = ron::de::from_reader(File::open(&args.matrix).unwrap()).unwrap();
+    let cases = matrix.cases();
+
+    std::fs::create_dir_all(&args.results).unwrap();
+    std::fs::copy(&args.matrix, args.results.join("matrix.ron")).unwrap();
+
+    let mut manifest = Manifest {
+        matrix: args.matrix.clone(),
+        client: args.client.clone().unwrap_or_else(|| sibling("bevy-edge")),
+        server: args.server.clone().unwrap_or_else(|| sibling("physics")),
+        runs: Vec::new(),
+    };
+
+    for (case, settings) in cases.iter().enumerate() {
+        let settings = localize(settings.clone(), args.port);
+
+        let skipped = matches!(
+            &settings.physics_plugin,
+            PhysicsPlugin::Server { partition: Some(_), .. } | PhysicsPlugin::Server { migration: Some(_), .. }
+        );
+        if skipped {
+            warn!("case {case} needs more than the one server the runner starts, skipping it");
+        }
+
+        for repetition in 0..args.repetitions {
+            let name = format!("{:03}_{}_{}", case, settings, repetition);
+            let dir = args.results.join(&name);
+            std::fs::create_dir_all(&dir).unwrap();
+
+            let file = File::create(dir.join("settings.ron")).unwrap();
+            ron::ser::to_writer_pretty(file, &settings, ron::ser::PrettyConfig::default()).unwrap();
+
+            let started = Instant::now();
+            let mut attempts = 0;
+            let outcome = loop {
+                if skipped {
+                    break Outcome::Skipped;
+                }
+
+                attempts += 1;
+                info!("running {name}, attempt {attempts}");
+
+                let outcome = run(&args, &manifest, &settings, &dir, attempts);
+                if outcome == Outcome::Succeeded || attempts > args.retries {
+                    break outcome;
+                }
+
+                warn!("run {name} did not succeed, {}", describe(outcome));
+            };
+
+            manifest.runs.push(Run {
+                name,
+                settings: settings.to_string(),
+                case,
+                repetition,
+                attempts,
+                outcome,
+                duration: started.elapsed().as_secs_f32(),
+            });
+
+            let file = File::create(args.results.join("manifest.ron")).unwrap();
+            ron::ser::to_writer_pretty(file, &manifest, ron::ser::PrettyConfig::default()).unwrap();
+        }
+    }
+
+    let skipped = manifest.runs.iter().filter(|run| run.outcome == Outcome::Skipped).count();
+    let failed = manifest.runs.iter().filter(|run| !matches!(run.outcome, Outcome::Succeeded | Outcome::Skipped)).count();
+    println!(
+        "{} runs of {} cases, {} failed, {} skipped, results in {}",
+        manifest.runs.len(), cases.len(), failed, skipped, args.results.display(),
+    );
+
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Starts the server the case needs and the client, and waits for the client to finish its bench.
+fn run(args: &Args, manifest: &Manifest, settings: &Settings, dir: &Path, attempt: u32) -> Outcome {
+    let server = match &settings.physics_plugin {
+        PhysicsPlugin::Server { transport, .. } => match Server::start(args, &manifest.server, transport, dir, attempt) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                warn!("failed to start the physics server, {e}");
+                return Outcome::ServerUnavailable;
+            }
+        },
+        PhysicsPlugin::Default => None,
+    };
+
+    let log = File::create(dir.join(format!("client.{attempt}.log"))).unwrap();
+    let mut client = Command::new(&manifest.client)
+        .arg(dir.join("settings.ron"))
+        .arg("--output")
+        .arg(dir)
+        .arg("--format")
+        .arg(&args.format)
+        .stdout(log.try_clone().unwrap())
+        .stderr(log)
+        .spawn()
+        .unwrap();
+
+    let timeout = Duration::from_secs_f32(settings.bench_length) + Duration::from_secs(args.timeout);
+    let outcome = match wait(&mut client, timeout) {
+        Some(status) if status.success() => Outcome::Succeeded,
+        Some(status) => Outcome::Failed(status.code()),
+        None => {
+            let _ = client.kill();
+            let _ = client.wait();
+            Outcome::TimedOut
+        }
+    };
+
+    if let Some(server) = server {
+        // The session of a client that failed may never end.
+        server.stop(outcome == Outcome::Succeeded);
+    }
+
+    outcome
+}
+
+struct Server {
+    process: Child,
+    /// Shared memory file of the server, which it leaves behind when it is killed.
+    shm: Option<PathBuf>,
+}
+
+impl Server {
+    /// Starts the server and waits until it accepts clients.
+    fn start(args: &Args, program: &Path, transport: &TransportKind, dir: &Path, attempt: u32) -> io::Result<Self> {
+        let mut command = Command::new(program);
+        command
+            .arg("--bind")
+            .arg("127.0.0.1")
+            .arg("--port")
+            .arg(args.port.to_string())
+            .arg("--quic-port")
+            .arg(quic_port(args.port).to_string())
+            .arg("--sessions")
+            .arg("1")
+            .arg("--no-discovery")
+            .arg("--trace-dir")
+            .arg(dir)
+            .arg("--snapshot-dir")
+            .arg(dir);
+
+        if let Some(config) = &args.server_config {
+            command.arg("--config").arg(config);
+        }
+
+        if let TransportKind::Shm { path: Some(path) } = transport {
+            command.arg("--shm").arg(path);
+        }
+        let shm = match transport {
+            TransportKind::Shm { path } => Some(path.as_ref().map_or_else(|| shared::transport::default_shm_path(args.port), PathBuf::from)),
+            _ => None,
+        };
+
+        let log = File::create(dir.join(format!("server.{attempt}.log")))?;
+        let mut process = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+        // Both streams go to the log, the server tells it is ready on its standard output.
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let stdout = BufReader::new(process.stdout.take().unwrap());
+        let mut stdout_log = log.try_clone()?;
+        std::thread::spawn(move || {
+            for line in stdout.lines().map_while(Result::ok) {
+                if line == SERVER_READY {
+                    let _ = ready_tx.send(());
+                }
+                let _ = stdout_log.write_all(format!("{line}\n").as_bytes());
+            }
+        });
+
+        let stderr = BufReader::new(process.stderr.take().unwrap());
+        let mut stderr_log = log;
+        std::thread::spawn(move || {
+            for line in stderr.lines().map_while(Result::ok) {
+                let _ = stderr_log.write_all(format!("{line}\n").as_bytes());
+            }
+        });
+
+        match ready_rx.recv_timeout(SERVER_START) {
+            Ok(()) => Ok(Server { process, shm }),
+            Err(e) => {
+                let _ = process.kill();
+                let _ = process.wait();
+                if let Some(path) = &shm {
+                    let _ = std::fs::remove_file(path);
+                }
+
+                let error = match e {
+                    mpsc::RecvTimeoutError::Timeout => io::Error::new(ErrorKind::TimedOut, "server did not bind its listeners in time"),
+                    mpsc::RecvTimeoutError::Disconnected => io::Error::other("server exited before it bound its listeners"),
+                };
+
+                Err(error)
+            }
+        }
+    }
+
+    /// Waits for the server to exit after the session if it finished, and kills it if it does not.
+    fn stop(mut self, finished: bool) {
+        if finished && wait(&mut self.process, SERVER_STOP).is_some() {
+            return;
+        }
+
+        if finished {
+            warn!("physics server did not exit after its session, killing it");
+        }
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        if let Some(path) = &self.shm {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Waits for the process to exit, none if it is still running after the timeout.
+fn wait(process: &mut Child, timeout: Duration) -> Option<ExitStatus> {
+    let started = Instant::now();
+
+    while started.elapsed() < timeout {
+        if let Some(status) = process.try_wait().unwrap() {
+            return Some(status);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    None
+}
+
+/// The case as it runs here, headless and against the server the runner starts.
+fn localize(mut settings: Settings, port: u16) -> Settings {
+    settings.headless = true;
+
+    if let PhysicsPlugin::Server { address, transport, .. } = &mut settings.physics_plugin {
+        let port = match transport {
+            TransportKind::Quic { .. } => quic_port(port),
+            _ => port,
+        };
+        *address = format!("127.0.0.1:{}", port);
+    }
+
+    settings
+}
+
+fn quic_port(port: u16) -> u16 {
+    port.checked_add(1).expect("there is no port after --port for quic")
+}
+
+/// The program next to the runner, where cargo builds all of the binaries of the workspace.
+fn sibling(name: &str) -> PathBuf {
+    let runner = std::env::current_exe().unwrap();
+    runner.with_file_name(format!("{}{}", name, std::env::consts::EXE_SUFFIX))
+}
+
+fn describe(outcome: Outcome) -> String {
+    match outcome {
+        Outcome::Succeeded => "succeeded".to_string(),
+        Outcome::Failed(Some(code)) => format!("client exited with {code}"),
+        Outcome::Failed(None) => "client was killed".to_string(),
+        Outcome::TimedOut => "client timed out".to_string(),
+        Outcome::ServerUnavailable => "physics server did not start".to_string(),
+        Outcome::Skipped => "case needs more than one server".to_string(),
+    }
+}
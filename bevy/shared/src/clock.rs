@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Exchanges the estimate of the offset is taken from, the least delayed of them is the most accurate.
+const WINDOW: usize = 8;
+
+/// Microseconds since the UNIX epoch on the clock of this machine.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros().try_into().unwrap()
+}
+
+/// Timestamps of one request and its response, in microseconds, the client's taken on its clock and
+/// the server's on its own.
+#[derive(Clone, Copy, Debug)]
+pub struct Exchange {
+    /// The request left the client.
+    pub sent: u64,
+    /// The request arrived at the server.
+    pub received: u64,
+    /// The response left the server.
+    pub responded: u64,
+    /// The response arrived at the client.
+    pub arrived: u64,
+}
+
+impl Exchange {
+    /// How far the clock of the server is ahead of the one of the client, as if the link took as
+    /// long in both directions.
+    pub fn offset(&self) -> i64 {
+        ((self.received as i64 - self.sent as i64) + (self.responded as i64 - self.arrived as i64)) / 2
+    }
+
+    /// Time spent on the link in both directions, without the time spent on the server.
+    pub fn delay(&self) -> i64 {
+        (self.arrived as i64 - self.sent as i64) - (self.responded as i64 - self.received as i64)
+    }
+}
+
+/// Latencies of an exchange, in microseconds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Latency {
+    pub uplink: u32,
+    /// Time the request spent on the server, between its arrival and the response leaving.
+    pub server: u32,
+    pub downlink: u32,
+}
+
+/// Estimates the offset between the clock of the client and the one of a server like NTP does, from
+/// the exchanges of the session.
+#[derive(Default)]
+pub struct ClockSync {
+    /// Offsets and delays of the latest exchanges.
+    samples: VecDeque<(i64, i64)>,
+    offset: i64,
+}
+
+impl ClockSync {
+    /// Estimated offset of the server's clock from the client's, in microseconds.
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    /// Takes the exchange into the estimate and splits it into the time spent in each direction.
+    pub fn update(&mut self, exchange: Exchange) -> Latency {
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((exchange.offset(), exchange.delay()));
+
+        // Queueing on the link only ever adds delay, so the least delayed exchange is the least skewed.
+        self.offset = self.samples.iter().min_by_key(|(_, delay)| *delay).map(|(offset, _)| *offset).unwrap();
+
+        let micros = |time: i64| time.clamp(0, u32::MAX as i64) as u32;
+
+        Latency {
+            uplink: micros(exchange.received as i64 - self.offset - exchange.sent as i64),
+            server: micros(exchange.responded as i64 - exchange.received as i64),
+            downlink: micros(exchange.arrived as i64 - (exchange.responded as i64 - self.offset)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Exchange starting at `sent` with a server whose clock is `offset` ahead, taking the given
+    /// times on the link and on the server.
+    fn exchange(sent: u64, offset: u64, uplink: u64, server: u64, downlink: u64) -> Exchange {
+        let received = sent + uplink + offset;
+        let responded = received + server;
+
+        Exchange { sent, received, responded, arrived: responded - offset + downlink }
+    }
+
+    #[test]
+    fn asymmetric_links_skew_the_offset_by_half_the_difference() {
+        let exchange = exchange(10_000, 1_000, 100, 50, 300);
+
+        assert_eq!(exchange.offset(), 1_000 - (300 - 100) / 2);
+        assert_eq!(exchange.delay(), 400);
+    }
+
+    #[test]
+    fn the_least_delayed_exchange_gives_the_offset() {
+        let mut clock = ClockSync::default();
+
+        let latency = clock.update(exchange(10_000, 1_000, 10, 50, 10));
+        assert_eq!(clock.offset(), 1_000);
+        assert_eq!((latency.uplink, latency.server, latency.downlink), (10, 50, 10));
+
+        let latency = clock.update(exchange(20_000, 1_000, 100, 50, 300));
+        assert_eq!(clock.offset(), 1_000);
+        assert_eq!((latency.uplink, latency.server, latency.downlink), (100, 50, 300));
+    }
+
+    #[test]
+    fn exchanges_out_of_the_window_are_forgotten() {
+        let mut clock = ClockSync::default();
+        clock.update(exchange(0, 1_000, 10, 50, 10));
+
+        // Each later exchange is more delayed than the first, and more on the downlink.
+        for index in 1..WINDOW as u64 {
+            clock.update(exchange(index * 10_000, 1_000, 20, 50, 20 + 2 * index));
+            assert_eq!(clock.offset(), 1_000);
+        }
+
+        clock.update(exchange(WINDOW as u64 * 10_000, 1_000, 20, 50, 1_000));
+        assert_eq!(clock.offset(), 1_000 - 1);
+    }
+}
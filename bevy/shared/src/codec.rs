@@ -0,0 +1,417 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use zstd::stream::raw::Operation;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum CodecKind {
+    None,
+    Deflate(u32),
+    Zstd(i32),
+    Lz4,
+}
+
+impl Display for CodecKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecKind::None => f.write_str("none"),
+            CodecKind::Deflate(level) => f.write_fmt(format_args!("deflate_{}", level)),
+            CodecKind::Zstd(level) => f.write_fmt(format_args!("zstd_{}", level)),
+            CodecKind::Lz4 => f.write_str("lz4"),
+        }
+    }
+}
+
+/// Describes what a codec keeps from the previous messages of the session.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum CodecContext {
+    /// Every message is compressed on its own.
+    #[default]
+    Reset,
+    /// The compression state is kept across messages and only sync-flushed at message boundaries.
+    Stream,
+    /// Every message is compressed with the previous message of the same direction as dictionary.
+    PreviousMessage,
+}
+
+impl Display for CodecContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecContext::Reset => f.write_str("reset"),
+            CodecContext::Stream => f.write_str("stream"),
+            CodecContext::PreviousMessage => f.write_str("previous"),
+        }
+    }
+}
+
+/// Returns the codec and context the server agrees to run for the requested ones. Compression
+/// levels are clamped into the range supported by the underlying library and a context the codec
+/// cannot run is replaced by the closest one it can.
+pub fn negotiate(kind: CodecKind, context: CodecContext) -> (CodecKind, CodecContext) {
+    match (kind, context) {
+        (CodecKind::None, _) => (CodecKind::None, CodecContext::Reset),
+        // flate2 can only set a dictionary with a zlib backend, streaming keeps the previous
+        // message in the window anyway.
+        (CodecKind::Deflate(level), CodecContext::PreviousMessage) => (CodecKind::Deflate(level.min(9)), CodecContext::Stream),
+        (CodecKind::Deflate(level), context) => (CodecKind::Deflate(level.min(9)), context),
+        (CodecKind::Zstd(level), context) => {
+            let range = zstd::compression_level_range();
+            (CodecKind::Zstd(level.clamp(*range.start(), *range.end())), context)
+        }
+        // lz4 blocks have no streaming state, linking them is the same as using the previous message as dictionary.
+        (CodecKind::Lz4, CodecContext::Stream) => (CodecKind::Lz4, CodecContext::PreviousMessage),
+        (CodecKind::Lz4, context) => (CodecKind::Lz4, context),
+    }
+}
+
+impl CodecKind {
+    pub fn build(self, context: CodecContext) -> Box<dyn Codec> {
+        match self {
+            CodecKind::None => Box::new(NoneCodec),
+            CodecKind::Deflate(level) => Box::new(DeflateCodec::new(level, context)),
+            CodecKind::Zstd(level) => Box::new(ZstdCodec::new(level, context)),
+            CodecKind::Lz4 => Box::new(Lz4Codec::new(context)),
+        }
+    }
+}
+
+/// Compresses and decompresses whole messages. Instances are kept for the whole session, so
+/// implementations may reuse their internal state between messages.
+pub trait Codec: Send {
+    fn kind(&self) -> CodecKind;
+
+    fn context(&self) -> CodecContext {
+        CodecContext::Reset
+    }
+
+    fn compress(&mut self, input: &[u8]) -> std::io::Result<Vec<u8>>;
+
+    fn decompress(&mut self, input: &[u8], raw_len: usize) -> std::io::Result<Vec<u8>>;
+}
+
+pub struct NoneCodec;
+
+impl Codec for NoneCodec {
+    fn kind(&self) -> CodecKind {
+        CodecKind::None
+    }
+
+    fn compress(&mut self, input: &[u8]) -> std::io::Result<Vec<u8>> {
+        Ok(input.to_vec())
+    }
+
+    fn decompress(&mut self, input: &[u8], _: usize) -> std::io::Result<Vec<u8>> {
+        Ok(input.to_vec())
+    }
+}
+
+pub struct DeflateCodec {
+    level: u32,
+    context: CodecContext,
+    comp: flate2::Compress,
+    decomp: flate2::Decompress,
+}
+
+impl DeflateCodec {
+    pub fn new(level: u32, context: CodecContext) -> Self {
+        DeflateCodec {
+            level,
+            context,
+            comp: flate2::Compress::new(flate2::Compression::new(level), true),
+            decomp: flate2::Decompress::new(true),
+        }
+    }
+}
+
+impl Codec for DeflateCodec {
+    fn kind(&self) -> CodecKind {
+        CodecKind::Deflate(self.level)
+    }
+
+    fn context(&self) -> CodecContext {
+        self.context
+    }
+
+    fn compress(&mut self, input: &[u8]) -> std::io::Result<Vec<u8>> {
+        let flush = match self.context {
+            CodecContext::Reset => {
+                self.comp.reset();
+                flate2::FlushCompress::Finish
+            }
+            _ => flate2::FlushCompress::Sync,
+        };
+
+        let before_in = self.comp.total_in();
+        let mut output = Vec::with_capacity(input.len() / 2 + 64);
+
+        loop {
+            if output.len() == output.capacity() {
+                output.reserve(input.len() / 2 + 64);
+            }
+
+            let consumed = (self.comp.total_in() - before_in) as usize;
+            let status = self.comp.compress_vec(&input[consumed..], &mut output, flush)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+            let consumed_all = (self.comp.total_in() - before_in) as usize == input.len();
+            let has_space = output.len() < output.capacity();
+
+            match (flush, status) {
+                (_, flate2::Status::StreamEnd) => break,
+                // The sync flush is complete once all the input is consumed without filling the output.
+                (flate2::FlushCompress::Sync, _) if consumed_all && has_space => break,
+                (_, flate2::Status::BufError) if has_space => {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "deflate could not make progress"));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn decompress(&mut self, input: &[u8], raw_len: usize) -> std::io::Result<Vec<u8>> {
+        let flush = match self.context {
+            CodecContext::Reset => {
+                self.decomp.reset(true);
+                flate2::FlushDecompress::Finish
+            }
+            _ => flate2::FlushDecompress::Sync,
+        };
+
+        let before_in = self.decomp.total_in();
+        let mut output = Vec::with_capacity(raw_len);
+
+        loop {
+            if output.len() == output.capacity() {
+                output.reserve(64);
+            }
+
+            let consumed = (self.decomp.total_in() - before_in) as usize;
+            let status = self.decomp.decompress_vec(&input[consumed..], &mut output, flush)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+            let consumed_all = (self.decomp.total_in() - before_in) as usize == input.len();
+
+            match status {
+                flate2::Status::StreamEnd => break,
+                _ if consumed_all && output.len() >= raw_len => break,
+                _ if consumed_all && output.len() < output.capacity() => {
+                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "deflate stream is truncated"));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+pub struct ZstdCodec {
+    level: i32,
+    context: CodecContext,
+    comp: zstd::bulk::Compressor<'static>,
+    decomp: zstd::bulk::Decompressor<'static>,
+    encoder: zstd::stream::raw::Encoder<'static>,
+    decoder: zstd::stream::raw::Decoder<'static>,
+    buffer: Vec<u8>,
+    previous_in: Vec<u8>,
+    previous_out: Vec<u8>,
+}
+
+impl ZstdCodec {
+    pub fn new(level: i32, context: CodecContext) -> Self {
+        ZstdCodec {
+            level,
+            context,
+            // zstd only fails to create its contexts when it cannot allocate them.
+            comp: zstd::bulk::Compressor::new(level).expect("zstd failed to allocate a compression context"),
+            decomp: zstd::bulk::Decompressor::new().expect("zstd failed to allocate a decompression context"),
+            encoder: zstd::stream::raw::Encoder::new(level).expect("zstd failed to allocate a compression stream"),
+            decoder: zstd::stream::raw::Decoder::new().expect("zstd failed to allocate a decompression stream"),
+            buffer: vec![0u8; 1024 * 8],
+            previous_in: Vec::new(),
+            previous_out: Vec::new(),
+        }
+    }
+
+    fn compress_stream(&mut self, input: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(input.len() / 2 + 64);
+        let mut consumed = 0;
+
+        while consumed < input.len() {
+            let status = self.encoder.run_on_buffers(&input[consumed..], &mut self.buffer)?;
+            consumed += status.bytes_read;
+            output.extend_from_slice(&self.buffer[..status.bytes_written]);
+        }
+
+        loop {
+            let mut out = zstd::stream::raw::OutBuffer::around(self.buffer.as_mut_slice());
+            let remaining = self.encoder.flush(&mut out)?;
+            let written = out.pos();
+            output.extend_from_slice(&self.buffer[..written]);
+
+            if remaining == 0 {
+                break;
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn decompress_stream(&mut self, input: &[u8], raw_len: usize) -> std::io::Result<Vec<u8>> {
+        let mut output = vec![0u8; raw_len];
+        let (mut consumed, mut written) = (0, 0);
+
+        while consumed < input.len() || written < raw_len {
+            let status = self.decoder.run_on_buffers(&input[consumed..], &mut output[written..])?;
+
+            if status.bytes_read == 0 && status.bytes_written == 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "zstd stream is truncated"));
+            }
+
+            consumed += status.bytes_read;
+            written += status.bytes_written;
+        }
+
+        Ok(output)
+    }
+}
+
+impl Codec for ZstdCodec {
+    fn kind(&self) -> CodecKind {
+        CodecKind::Zstd(self.level)
+    }
+
+    fn context(&self) -> CodecContext {
+        self.context
+    }
+
+    fn compress(&mut self, input: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self.context {
+            CodecContext::Reset => self.comp.compress(input),
+            CodecContext::Stream => self.compress_stream(input),
+            CodecContext::PreviousMessage => {
+                self.comp.set_dictionary(self.level, &self.previous_in)?;
+                let output = self.comp.compress(input)?;
+                self.previous_in = input.to_vec();
+
+                Ok(output)
+            }
+        }
+    }
+
+    fn decompress(&mut self, input: &[u8], raw_len: usize) -> std::io::Result<Vec<u8>> {
+        match self.context {
+            CodecContext::Reset => self.decomp.decompress(input, raw_len),
+            CodecContext::Stream => self.decompress_stream(input, raw_len),
+            CodecContext::PreviousMessage => {
+                self.decomp.set_dictionary(&self.previous_out)?;
+                let output = self.decomp.decompress(input, raw_len)?;
+                self.previous_out = output.clone();
+
+                Ok(output)
+            }
+        }
+    }
+}
+
+/// lz4 looks back at most this many bytes, so only the tail of the previous message is used as dictionary.
+const LZ4_WINDOW: usize = 64 * 1024;
+
+pub struct Lz4Codec {
+    context: CodecContext,
+    previous_in: Vec<u8>,
+    previous_out: Vec<u8>,
+}
+
+impl Lz4Codec {
+    pub fn new(context: CodecContext) -> Self {
+        Lz4Codec { context, previous_in: Vec::new(), previous_out: Vec::new() }
+    }
+}
+
+impl Codec for Lz4Codec {
+    fn kind(&self) -> CodecKind {
+        CodecKind::Lz4
+    }
+
+    fn context(&self) -> CodecContext {
+        self.context
+    }
+
+    fn compress(&mut self, input: &[u8]) -> std::io::Result<Vec<u8>> {
+        if let CodecContext::Reset = self.context {
+            return Ok(lz4_flex::block::compress(input));
+        }
+
+        let dict = &self.previous_in[self.previous_in.len().saturating_sub(LZ4_WINDOW)..];
+        let output = lz4_flex::block::compress_with_dict(input, dict);
+        self.previous_in = input.to_vec();
+
+        Ok(output)
+    }
+
+    fn decompress(&mut self, input: &[u8], raw_len: usize) -> std::io::Result<Vec<u8>> {
+        if let CodecContext::Reset = self.context {
+            return lz4_flex::block::decompress(input, raw_len)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+        }
+
+        let dict = &self.previous_out[self.previous_out.len().saturating_sub(LZ4_WINDOW)..];
+        let output = lz4_flex::block::decompress_with_dict(input, raw_len, dict)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        self.previous_out = output.clone();
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    const KINDS: [CodecKind; 4] = [CodecKind::None, CodecKind::Deflate(3), CodecKind::Zstd(3), CodecKind::Lz4];
+    const CONTEXTS: [CodecContext; 3] = [CodecContext::Reset, CodecContext::Stream, CodecContext::PreviousMessage];
+
+    /// Messages from 1 B to 300 KB, past the window of lz4 and the buffer of zstd, each one a
+    /// slightly changed copy of the one before so that the contexts have something to reuse.
+    fn messages() -> Vec<Vec<u8>> {
+        let mut rng = StdRng::seed_from_u64(27);
+        let sizes = [1, 17, 1000, 8 * 1024 + 1, LZ4_WINDOW + 100, 300_000];
+        let mut base: Vec<u8> = (0..300_000).map(|i| (i % 251) as u8).collect();
+
+        (0..50)
+            .map(|i| {
+                for _ in 0..200 {
+                    let index = rng.gen_range(0..base.len());
+                    base[index] = rng.gen();
+                }
+
+                base[..sizes[i % sizes.len()]].to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_kind_and_context_round_trips() {
+        let messages = messages();
+
+        for kind in KINDS {
+            for context in CONTEXTS {
+                let mut sender = kind.build(context);
+                let mut receiver = kind.build(context);
+
+                for (index, message) in messages.iter().enumerate() {
+                    let compressed = sender.compress(message).unwrap();
+                    let decompressed = receiver.decompress(&compressed, message.len()).unwrap();
+
+                    assert!(decompressed == *message, "{kind} with {context} context corrupted message {index} of {} bytes", message.len());
+                }
+            }
+        }
+    }
+}
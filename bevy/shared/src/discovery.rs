@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::transport::TransportKind;
+use crate::CONFIG;
+
+/// Port the responders listen on for probes.
+pub const DISCOVERY_PORT: u16 = 4000;
+/// Multicast group the probes are sent to, scoped to the organization.
+pub const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 66, 69);
+/// Address in the settings that asks for a server to be discovered instead.
+pub const AUTO: &str = "auto";
+
+const MAGIC: [u8; 4] = *b"BEDP";
+
+/// What a responder offers, the physics server or the input listener of a client.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Service {
+    Physics,
+    Input,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Announcement {
+    pub service: Service,
+    /// Port of TCP or TLS and UDP.
+    pub port: u16,
+    pub quic_port: Option<u16>,
+    pub tls: bool,
+    pub token_required: bool,
+    pub active_sessions: u32,
+    pub max_sessions: u32,
+}
+
+impl Announcement {
+    pub fn is_full(&self) -> bool {
+        self.active_sessions >= self.max_sessions
+    }
+
+    /// Port of the server for the transport.
+    pub fn port(&self, transport: &TransportKind) -> u16 {
+        match transport {
+            TransportKind::Quic { .. } => self.quic_port.unwrap_or(self.port.saturating_add(1)),
+            _ => self.port,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+enum Message {
+    Probe(Service),
+    Announce(Announcement),
+}
+
+fn encode(message: &Message) -> Vec<u8> {
+    let mut buf = MAGIC.to_vec();
+    buf.extend(bincode::serde::encode_to_vec(message, CONFIG).unwrap());
+
+    buf
+}
+
+fn decode(buf: &[u8]) -> Option<Message> {
+    let payload = buf.strip_prefix(&MAGIC)?;
+
+    bincode::serde::decode_from_slice(payload, CONFIG).ok().map(|(message, _)| message)
+}
+
+/// Answers the probes for its service with the announcement `announce` returns at that moment.
+/// Several responders can run on the same host, every one of them receives the probes.
+pub fn respond<F>(service: Service, announce: F) -> io::Result<()>
+where
+    F: Fn() -> Announcement + Send + 'static,
+{
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)).into())?;
+
+    let socket: UdpSocket = socket.into();
+    if let Err(e) = socket.join_multicast_v4(&MULTICAST_GROUP, &Ipv4Addr::UNSPECIFIED) {
+        // Broadcast probes still arrive.
+        debug!("failed to join the discovery multicast group, {e}");
+    }
+
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 512];
+
+        loop {
+            let (len, peer) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) => {
+                    log::error!("failed to receive discovery probe, {e}");
+                    return;
+                }
+            };
+
+            match decode(&buf[..len]) {
+                Some(Message::Probe(probed)) if probed == service => {
+                    trace!("answering discovery probe of {peer}");
+
+                    if let Err(e) = socket.send_to(&encode(&Message::Announce(announce())), peer) {
+                        debug!("failed to answer discovery probe of {peer}, {e}");
+                    }
+                }
+                _ => {}
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Responder that answered a probe.
+#[derive(Clone, Debug)]
+pub struct Server {
+    pub ip: std::net::IpAddr,
+    pub announcement: Announcement,
+    /// Time from the probe until the answer arrived.
+    pub latency: Duration,
+}
+
+impl Server {
+    /// Address to connect to with the transport.
+    pub fn address(&self, transport: &TransportKind) -> String {
+        SocketAddr::new(self.ip, self.announcement.port(transport)).to_string()
+    }
+}
+
+/// Probes the multicast group and the broadcast address and collects the answers arriving within
+/// `wait`, quickest first.
+pub fn discover(service: Service, wait: Duration) -> io::Result<Vec<Server>> {
+    discover_at(
+        &[
+            SocketAddrV4::new(MULTICAST_GROUP, DISCOVERY_PORT).into(),
+            SocketAddrV4::new(Ipv4Addr::BROADCAST, DISCOVERY_PORT).into(),
+        ],
+        service,
+        wait,
+    )
+}
+
+/// Probes the given addresses, such as a single host or the loopback address. A unicast probe
+/// reaches only one of the responders of a host.
+pub fn discover_at(targets: &[SocketAddr], service: Service, wait: Duration) -> io::Result<Vec<Server>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_broadcast(true)?;
+
+    let probe = encode(&Message::Probe(service));
+    let sent_at = Instant::now();
+    let mut sent = false;
+
+    for target in targets {
+        match socket.send_to(&probe, target) {
+            Ok(_) => sent = true,
+            // Networks without a route for multicast or broadcast are not an error as long as
+            // some probe leaves.
+            Err(e) => debug!("failed to send discovery probe to {target}, {e}"),
+        }
+    }
+
+    if !sent {
+        return Err(io::Error::new(ErrorKind::NotConnected, "no discovery probe could be sent"));
+    }
+
+    // A responder receives the probe once per target it listens on.
+    let mut servers = HashMap::new();
+    let mut buf = [0u8; 512];
+
+    while let Some(left) = wait.checked_sub(sent_at.elapsed()).filter(|left| !left.is_zero()) {
+        socket.set_read_timeout(Some(left))?;
+
+        let (len, peer) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            Err(e) => return Err(e),
+        };
+
+        if let Some(Message::Announce(announcement)) = decode(&buf[..len]) {
+            if announcement.service == service {
+                servers
+                    .entry((peer.ip(), announcement.port))
+                    .or_insert(Server { ip: peer.ip(), announcement, latency: sent_at.elapsed() });
+            }
+        }
+    }
+
+    let mut servers: Vec<Server> = servers.into_values().collect();
+    servers.sort_by_key(|server| server.latency);
+
+    Ok(servers)
+}
+
+/// The quickest server that can take another session, or the quickest one if all are full.
+pub fn pick(service: Service, wait: Duration) -> io::Result<Option<Server>> {
+    let servers = discover(service, wait)?;
+
+    for server in &servers {
+        debug!("discovered {:?} at {} with {}/{} sessions, {:?} away", service, server.ip, server.announcement.active_sessions, server.announcement.max_sessions, server.latency);
+    }
+
+    Ok(servers.iter().find(|server| !server.announcement.is_full()).or(servers.first()).cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback_probe_finds_the_responder_of_its_service() {
+        let port = 47123;
+        respond(Service::Physics, move || Announcement {
+            service: Service::Physics,
+            port,
+            quic_port: Some(port + 1),
+            tls: false,
+            token_required: true,
+            active_sessions: 1,
+            max_sessions: 4,
+        })
+        .unwrap();
+
+        let targets = [SocketAddr::from((Ipv4Addr::LOCALHOST, DISCOVERY_PORT))];
+        let wait = Duration::from_millis(300);
+
+        let servers = discover_at(&targets, Service::Physics, wait).unwrap();
+        let server = servers.iter().find(|server| server.announcement.port == port).unwrap();
+        assert_eq!(server.address(&TransportKind::Tcp), format!("127.0.0.1:{port}"));
+        assert_eq!(server.address(&TransportKind::Quic { certificate: None, insecure: false }), format!("127.0.0.1:{}", port + 1));
+        assert!(server.announcement.token_required);
+
+        let servers = discover_at(&targets, Service::Input, wait).unwrap();
+        assert!(servers.iter().all(|server| server.announcement.port != port));
+    }
+}
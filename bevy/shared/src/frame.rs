@@ -0,0 +1,360 @@
+use std::fmt::Display;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use log::trace;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::codec::Codec;
+use crate::CONFIG;
+
+const MAGIC: [u8; 2] = *b"BE";
+const FLAG_CHECKSUM: u8 = 1;
+/// Anything above this is treated as a corrupted length rather than allocated.
+const MAX_LENGTH: u32 = 256 * 1024 * 1024;
+
+pub const HEADER_SIZE: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageKind {
+    Settings,
+    Handshake,
+    Request,
+    Response,
+    Log,
+    /// Reports of a physics server to the broker.
+    Registry,
+    /// Sessions moving from one physics server to another.
+    Migration,
+}
+
+impl MessageKind {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(MessageKind::Settings),
+            1 => Some(MessageKind::Handshake),
+            2 => Some(MessageKind::Request),
+            3 => Some(MessageKind::Response),
+            4 => Some(MessageKind::Log),
+            5 => Some(MessageKind::Registry),
+            6 => Some(MessageKind::Migration),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            MessageKind::Settings => 0,
+            MessageKind::Handshake => 1,
+            MessageKind::Request => 2,
+            MessageKind::Response => 3,
+            MessageKind::Log => 4,
+            MessageKind::Registry => 5,
+            MessageKind::Migration => 6,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(std::io::Error),
+    BadMagic([u8; 2]),
+    UnknownKind(u8),
+    UnexpectedKind { expected: MessageKind, received: MessageKind },
+    OutOfSequence { expected: u32, received: u32 },
+    Checksum { expected: u32, computed: u32 },
+    TooLarge(usize),
+    Codec(std::io::Error),
+    Encode(bincode::error::EncodeError),
+    Decode(bincode::error::DecodeError),
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::Io(e) => f.write_fmt(format_args!("io error, {e}")),
+            FrameError::BadMagic(magic) => f.write_fmt(format_args!("stream is misaligned, received magic {magic:?}")),
+            FrameError::UnknownKind(kind) => f.write_fmt(format_args!("unknown message kind {kind}")),
+            FrameError::UnexpectedKind { expected, received } => f.write_fmt(format_args!("expected {expected:?} message, received {received:?}")),
+            FrameError::OutOfSequence { expected, received } => f.write_fmt(format_args!("expected frame {expected}, received {received}")),
+            FrameError::Checksum { expected, computed } => f.write_fmt(format_args!("corrupted frame, checksum {computed:#010x} does not match {expected:#010x}")),
+            FrameError::TooLarge(length) => f.write_fmt(format_args!("frame length {length} is too large")),
+            FrameError::Codec(e) => f.write_fmt(format_args!("codec failed, {e}")),
+            FrameError::Encode(e) => f.write_fmt(format_args!("failed to encode message, {e}")),
+            FrameError::Decode(e) => f.write_fmt(format_args!("failed to decode message, {e}")),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<std::io::Error> for FrameError {
+    fn from(e: std::io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+#[derive(Debug)]
+pub struct Header {
+    pub kind: MessageKind,
+    pub sequence: u32,
+    pub length: u32,
+    pub raw_length: u32,
+    pub checksum: Option<u32>,
+}
+
+impl Header {
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0u8; HEADER_SIZE];
+
+        buf[..2].copy_from_slice(&MAGIC);
+        buf[2] = self.kind.to_u8();
+        buf[3] = if self.checksum.is_some() { FLAG_CHECKSUM } else { 0 };
+        buf[4..8].copy_from_slice(&self.sequence.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.length.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.raw_length.to_le_bytes());
+        buf[16..].copy_from_slice(&self.checksum.unwrap_or(0).to_le_bytes());
+
+        buf
+    }
+
+    pub fn from_bytes(buf: [u8; HEADER_SIZE]) -> Result<Self, FrameError> {
+        if buf[..2] != MAGIC {
+            return Err(FrameError::BadMagic([buf[0], buf[1]]));
+        }
+
+        let kind = MessageKind::from_u8(buf[2]).ok_or(FrameError::UnknownKind(buf[2]))?;
+        let length = u32::from_le_bytes(buf[8..12].try_into().unwrap());
+        // The codecs allocate the raw length up front.
+        let raw_length = u32::from_le_bytes(buf[12..16].try_into().unwrap());
+
+        if length > MAX_LENGTH {
+            return Err(FrameError::TooLarge(length as usize));
+        }
+
+        if raw_length > MAX_LENGTH {
+            return Err(FrameError::TooLarge(raw_length as usize));
+        }
+
+        Ok(Header {
+            kind,
+            sequence: u32::from_le_bytes(buf[4..8].try_into().unwrap()),
+            length,
+            raw_length,
+            checksum: if buf[3] & FLAG_CHECKSUM != 0 { Some(u32::from_le_bytes(buf[16..].try_into().unwrap())) } else { None },
+        })
+    }
+}
+
+pub struct Frame {
+    pub header: Header,
+    pub payload: Vec<u8>,
+}
+
+/// Sizes of a single message on both sides of the codec, the time spent in the codec and the time
+/// the transport took to take or hand over its frame, in microseconds.
+#[derive(Default)]
+pub struct Stats {
+    pub raw: u64,
+    pub compressed: u64,
+    pub elapsed: u32,
+    pub transfer: u32,
+}
+
+/// Microseconds of the duration, the largest a log holds if it is longer.
+pub fn micros(duration: Duration) -> u32 {
+    duration.as_micros().try_into().unwrap_or(u32::MAX)
+}
+
+/// Serializes and compresses a message into the payload of a frame.
+pub fn encode<T: Serialize>(codec: &mut dyn Codec, message: &T) -> Result<(Vec<u8>, Stats), FrameError> {
+    let raw = bincode::serde::encode_to_vec(message, CONFIG).map_err(FrameError::Encode)?;
+
+    let instant = Instant::now();
+    let compressed = codec.compress(&raw).map_err(FrameError::Codec)?;
+    let elapsed = micros(instant.elapsed());
+
+    let stats = Stats { raw: raw.len() as u64, compressed: compressed.len() as u64, elapsed, transfer: 0 };
+
+    Ok((compressed, stats))
+}
+
+/// Decompresses and deserializes the payload of a frame, which has to be of the given kind.
+pub fn decode<T: DeserializeOwned>(codec: &mut dyn Codec, frame: &Frame, kind: MessageKind) -> Result<(T, Stats), FrameError> {
+    if frame.header.kind != kind {
+        return Err(FrameError::UnexpectedKind { expected: kind, received: frame.header.kind });
+    }
+
+    let instant = Instant::now();
+    let raw = codec.decompress(&frame.payload, frame.header.raw_length as usize).map_err(FrameError::Codec)?;
+    let elapsed = micros(instant.elapsed());
+
+    let (message, _) = bincode::serde::decode_from_slice(&raw, CONFIG).map_err(FrameError::Decode)?;
+
+    Ok((message, Stats { raw: raw.len() as u64, compressed: frame.payload.len() as u64, elapsed, transfer: 0 }))
+}
+
+/// Writes the frames of one direction of a connection, numbering them in order.
+pub struct FrameWriter {
+    sequence: u32,
+    checksum: bool,
+}
+
+impl FrameWriter {
+    pub fn new(checksum: bool) -> Self {
+        FrameWriter { sequence: 0, checksum }
+    }
+
+    pub fn set_checksum(&mut self, checksum: bool) {
+        self.checksum = checksum;
+    }
+
+    pub fn write_frame<W: Write>(&mut self, mut dest: W, kind: MessageKind, payload: &[u8], raw_length: usize) -> Result<(), FrameError> {
+        let header = Header {
+            kind,
+            sequence: self.sequence,
+            length: checked_length(payload.len())?,
+            raw_length: checked_length(raw_length)?,
+            checksum: if self.checksum { Some(crc32fast::hash(payload)) } else { None },
+        };
+        trace!("Sending frame {header:?}");
+
+        let mut buf = Vec::with_capacity(HEADER_SIZE + payload.len());
+        buf.extend_from_slice(&header.to_bytes());
+        buf.extend_from_slice(payload);
+        dest.write_all(&buf)?;
+        dest.flush()?;
+
+        self.sequence = self.sequence.wrapping_add(1);
+
+        Ok(())
+    }
+}
+
+/// The peer rejects frames above [`MAX_LENGTH`], they are not sent at all.
+fn checked_length(length: usize) -> Result<u32, FrameError> {
+    match u32::try_from(length) {
+        Ok(length) if length <= MAX_LENGTH => Ok(length),
+        _ => Err(FrameError::TooLarge(length)),
+    }
+}
+
+/// Reads the frames of one direction of a connection, verifying their order and checksum.
+#[derive(Default)]
+pub struct FrameReader {
+    sequence: u32,
+}
+
+impl FrameReader {
+    pub fn new() -> Self {
+        FrameReader { sequence: 0 }
+    }
+
+    pub fn read_frame<R: Read>(&mut self, mut source: R) -> Result<Frame, FrameError> {
+        let mut bytes = [0u8; HEADER_SIZE];
+        source.read_exact(&mut bytes)?;
+        let header = Header::from_bytes(bytes)?;
+        trace!("Received frame {header:?}");
+
+        if header.sequence != self.sequence {
+            return Err(FrameError::OutOfSequence { expected: self.sequence, received: header.sequence });
+        }
+
+        let mut payload = vec![0u8; header.length as usize];
+        source.read_exact(&mut payload)?;
+
+        if let Some(expected) = header.checksum {
+            let computed = crc32fast::hash(&payload);
+            if computed != expected {
+                return Err(FrameError::Checksum { expected, computed });
+            }
+        }
+
+        self.sequence = self.sequence.wrapping_add(1);
+
+        Ok(Frame { header, payload })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(writer: &mut FrameWriter, kind: MessageKind, payload: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        writer.write_frame(&mut buf, kind, payload, payload.len()).unwrap();
+        buf
+    }
+
+    #[test]
+    fn header_round_trips() {
+        let header = Header { kind: MessageKind::Response, sequence: 7, length: 1200, raw_length: 4000, checksum: Some(0xdead_beef) };
+        let read = Header::from_bytes(header.to_bytes()).unwrap();
+
+        assert_eq!(read.kind, MessageKind::Response);
+        assert_eq!(read.sequence, 7);
+        assert_eq!(read.length, 1200);
+        assert_eq!(read.raw_length, 4000);
+        assert_eq!(read.checksum, Some(0xdead_beef));
+
+        let header = Header { checksum: None, ..header };
+        assert_eq!(Header::from_bytes(header.to_bytes()).unwrap().checksum, None);
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut buf = written(&mut FrameWriter::new(false), MessageKind::Request, b"payload");
+        buf[0] = b'X';
+
+        assert!(matches!(FrameReader::new().read_frame(buf.as_slice()), Err(FrameError::BadMagic([b'X', b'E']))));
+    }
+
+    #[test]
+    fn frames_out_of_sequence_are_rejected() {
+        let mut writer = FrameWriter::new(false);
+        let _first = written(&mut writer, MessageKind::Request, b"first");
+        let second = written(&mut writer, MessageKind::Request, b"second");
+
+        let result = FrameReader::new().read_frame(second.as_slice());
+        assert!(matches!(result, Err(FrameError::OutOfSequence { expected: 0, received: 1 })));
+    }
+
+    #[test]
+    fn flipped_payload_byte_fails_the_checksum() {
+        let mut buf = written(&mut FrameWriter::new(true), MessageKind::Request, b"payload");
+        buf[HEADER_SIZE + 3] ^= 0x40;
+
+        assert!(matches!(FrameReader::new().read_frame(buf.as_slice()), Err(FrameError::Checksum { .. })));
+
+        // Without the checksum the corruption goes through.
+        let mut buf = written(&mut FrameWriter::new(false), MessageKind::Request, b"payload");
+        buf[HEADER_SIZE + 3] ^= 0x40;
+
+        assert_eq!(FrameReader::new().read_frame(buf.as_slice()).unwrap().payload, b"pay,oad");
+    }
+
+    #[test]
+    fn oversized_lengths_are_rejected() {
+        let mut buf = written(&mut FrameWriter::new(false), MessageKind::Request, b"payload");
+        buf[8..12].copy_from_slice(&(MAX_LENGTH + 1).to_le_bytes());
+        assert!(matches!(FrameReader::new().read_frame(buf.as_slice()), Err(FrameError::TooLarge(_))));
+
+        let mut writer = FrameWriter::new(false);
+        let result = writer.write_frame(Vec::new(), MessageKind::Request, b"payload", MAX_LENGTH as usize + 1);
+        assert!(matches!(result, Err(FrameError::TooLarge(_))));
+
+        // The rejected frame does not take a sequence number.
+        let buf = written(&mut writer, MessageKind::Request, b"payload");
+        assert_eq!(FrameReader::new().read_frame(buf.as_slice()).unwrap().header.sequence, 0);
+    }
+
+    #[test]
+    fn truncated_payload_is_an_unexpected_eof() {
+        let buf = written(&mut FrameWriter::new(true), MessageKind::Request, b"payload");
+
+        match FrameReader::new().read_frame(&buf[..buf.len() - 2]) {
+            Err(FrameError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof),
+            _ => panic!("truncated frame was read"),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::codec::{CodecContext, CodecKind};
+
+/// Answer of the server, or the broker, to the settings of a client.
+#[derive(Deserialize, Serialize)]
+pub enum Handshake {
+    Accepted { codec: CodecKind, codec_context: CodecContext },
+    /// Sent by the broker, the client starts over with the server at the address.
+    Redirect { address: String },
+    Rejected(Rejection),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum Rejection {
+    MissingToken,
+    InvalidToken,
+    Busy,
+    /// No session moved to the server waits for the ticket the client presented.
+    UnknownSession,
+    /// The server a session is moved to cannot be reached.
+    Unreachable,
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::MissingToken => f.write_str("the server requires a token and none is given"),
+            Rejection::InvalidToken => f.write_str("the given token is not accepted by the server"),
+            Rejection::Busy => f.write_str("the server is already serving as many sessions as it allows"),
+            Rejection::UnknownSession => f.write_str("no session moved to the server waits for the given ticket"),
+            Rejection::Unreachable => f.write_str("the server the session is moved to cannot be reached"),
+        }
+    }
+}
+
+/// Checks the token of a client against the one the server expects, if it expects any.
+pub fn authenticate(expected: Option<&str>, given: Option<&str>) -> Result<(), Rejection> {
+    match (expected, given) {
+        (None, _) => Ok(()),
+        (Some(_), None) => Err(Rejection::MissingToken),
+        (Some(expected), Some(given)) if matches(expected.as_bytes(), given.as_bytes()) => Ok(()),
+        (Some(_), Some(_)) => Err(Rejection::InvalidToken),
+    }
+}
+
+/// Compares without returning early, so the time it takes does not tell how much of a guess is right.
+fn matches(expected: &[u8], given: &[u8]) -> bool {
+    expected.len() == given.len() && expected.iter().zip(given).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -1,4 +1,31 @@
-pub mod deflate;
+pub mod clock;
+pub mod codec;
+pub mod discovery;
+pub mod frame;
+pub mod handshake;
+pub mod matrix;
+pub mod migration;
+pub mod partition;
+pub mod recording;
+pub mod registry;
 pub mod request;
 pub mod response;
+pub mod scene;
 pub mod settings;
+pub mod state;
+pub mod transport;
+
+pub const CONFIG: bincode::config::Configuration = bincode::config::standard();
+
+/// Printed by the physics server on its standard output once all of its listeners are bound, for
+/// the programs that start it to know when it accepts clients.
+pub const SERVER_READY: &str = "physics server is ready";
+
+/// Logs to the standard error as RUST_LOG tells, at the level if one is given.
+pub fn init_logger(level: Option<log::LevelFilter>) {
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(level) = level {
+        logger.filter_level(level);
+    }
+    logger.init();
+}
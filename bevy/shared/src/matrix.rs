@@ -0,0 +1,263 @@
+use serde::{Deserialize, Serialize};
+
+use crate::codec::{CodecContext, CodecKind};
+use crate::migration::Migration;
+use crate::partition::Partition;
+use crate::settings::{Lockstep, PhysicsPlugin, Room, Settings};
+use crate::transport::{NetworkEmulation, TransportKind};
+use crate::CONFIG;
+
+/// Cases of a benchmark, the base settings with every combination of the values of the axes.
+#[derive(Deserialize, Serialize)]
+pub struct Matrix {
+    pub base: Settings,
+    /// Applied in order, an axis overrides what the ones before it set on the same field.
+    #[serde(default)]
+    pub axes: Vec<Axis>,
+    /// Cases with one of the values of every axis of one of the filters are left out.
+    #[serde(default)]
+    pub exclude: Vec<Vec<Axis>>,
+    /// Only the cases with one of the values of every axis of one of the filters are kept, all of
+    /// them if there are none.
+    #[serde(default)]
+    pub only: Vec<Vec<Axis>>,
+}
+
+/// Values a field of the settings takes in the cases, in order. The fields of the server only
+/// exist in the cases that offload the physics.
+#[derive(Deserialize, Serialize)]
+pub enum Axis {
+    TracingLevel(Vec<Option<String>>),
+    Headless(Vec<bool>),
+    PhysicsPlugin(Vec<PhysicsPlugin>),
+    BenchLength(Vec<f32>),
+    Camera(Vec<(f32, f32, f32)>),
+    NumObject(Vec<usize>),
+    Shape(Vec<String>),
+    Restitution(Vec<f32>),
+    Room(Vec<Room>),
+    Ccd(Vec<bool>),
+    Codec(Vec<CodecKind>),
+    CodecContext(Vec<CodecContext>),
+    Transport(Vec<TransportKind>),
+    Checksum(Vec<bool>),
+    GenerateScene(Vec<bool>),
+    Token(Vec<Option<String>>),
+    Partition(Vec<Option<Partition>>),
+    Migration(Vec<Option<Migration>>),
+    Record(Vec<Option<String>>),
+    Lockstep(Vec<Option<Lockstep>>),
+    Network(Vec<Option<NetworkEmulation>>),
+}
+
+/// Axes whose values are options, for formats without a value for none such as TOML.
+pub const OPTION_AXES: [&str; 7] = ["TracingLevel", "Token", "Partition", "Migration", "Record", "Lockstep", "Network"];
+
+/// Fields of the settings of a server that the axes set.
+struct Server<'a> {
+    codec: &'a mut CodecKind,
+    codec_context: &'a mut CodecContext,
+    transport: &'a mut TransportKind,
+    checksum: &'a mut bool,
+    generate_scene: &'a mut bool,
+    token: &'a mut Option<String>,
+    partition: &'a mut Option<Partition>,
+    migration: &'a mut Option<Migration>,
+    record: &'a mut Option<String>,
+    lockstep: &'a mut Option<Lockstep>,
+    network: &'a mut Option<NetworkEmulation>,
+}
+
+impl Axis {
+    fn len(&self) -> usize {
+        match self {
+            Axis::TracingLevel(values) => values.len(),
+            Axis::Headless(values) => values.len(),
+            Axis::PhysicsPlugin(values) => values.len(),
+            Axis::BenchLength(values) => values.len(),
+            Axis::Camera(values) => values.len(),
+            Axis::NumObject(values) => values.len(),
+            Axis::Shape(values) => values.len(),
+            Axis::Restitution(values) => values.len(),
+            Axis::Room(values) => values.len(),
+            Axis::Ccd(values) => values.len(),
+            Axis::Codec(values) => values.len(),
+            Axis::CodecContext(values) => values.len(),
+            Axis::Transport(values) => values.len(),
+            Axis::Checksum(values) => values.len(),
+            Axis::GenerateScene(values) => values.len(),
+            Axis::Token(values) => values.len(),
+            Axis::Partition(values) => values.len(),
+            Axis::Migration(values) => values.len(),
+            Axis::Record(values) => values.len(),
+            Axis::Lockstep(values) => values.len(),
+            Axis::Network(values) => values.len(),
+        }
+    }
+
+    /// Sets the field of the axis to the value at the index, or tells whether it holds one of the
+    /// values without an index. False if the settings have no such field.
+    fn visit(&self, settings: &mut Settings, index: Option<usize>) -> bool {
+        let scene = &mut settings.scene;
+        let server = match &mut settings.physics_plugin {
+            PhysicsPlugin::Server { codec, codec_context, transport, checksum, generate_scene, token, partition, migration, record, lockstep, network, .. } => {
+                Some(Server { codec, codec_context, transport, checksum, generate_scene, token, partition, migration, record, lockstep, network })
+            }
+            PhysicsPlugin::Default => None,
+        };
+
+        match self {
+            Axis::TracingLevel(values) => field(Some(&mut settings.tracing_level), values, index),
+            Axis::Headless(values) => field(Some(&mut settings.headless), values, index),
+            Axis::PhysicsPlugin(values) => field(Some(&mut settings.physics_plugin), values, index),
+            Axis::BenchLength(values) => field(Some(&mut settings.bench_length), values, index),
+            Axis::Camera(values) => field(Some(&mut scene.camera), values, index),
+            Axis::NumObject(values) => field(Some(&mut scene.num_object), values, index),
+            Axis::Shape(values) => field(Some(&mut scene.shape), values, index),
+            Axis::Restitution(values) => field(Some(&mut scene.restitution), values, index),
+            Axis::Room(values) => field(Some(&mut scene.room), values, index),
+            Axis::Ccd(values) => field(Some(&mut scene.ccd), values, index),
+            Axis::Codec(values) => field(server.map(|server| server.codec), values, index),
+            Axis::CodecContext(values) => field(server.map(|server| server.codec_context), values, index),
+            Axis::Transport(values) => field(server.map(|server| server.transport), values, index),
+            Axis::Checksum(values) => field(server.map(|server| server.checksum), values, index),
+            Axis::GenerateScene(values) => field(server.map(|server| server.generate_scene), values, index),
+            Axis::Token(values) => field(server.map(|server| server.token), values, index),
+            Axis::Partition(values) => field(server.map(|server| server.partition), values, index),
+            Axis::Migration(values) => field(server.map(|server| server.migration), values, index),
+            Axis::Record(values) => field(server.map(|server| server.record), values, index),
+            Axis::Lockstep(values) => field(server.map(|server| server.lockstep), values, index),
+            Axis::Network(values) => field(server.map(|server| server.network), values, index),
+        }
+    }
+}
+
+fn field<T: Clone + Serialize>(field: Option<&mut T>, values: &[T], index: Option<usize>) -> bool {
+    match (field, index) {
+        (Some(field), Some(index)) => {
+            *field = values[index].clone();
+            true
+        }
+        // Not all of the settings can be compared, their encodings can.
+        (Some(field), None) => {
+            let encoded = bincode::serde::encode_to_vec(&*field, CONFIG).unwrap();
+            values.iter().any(|value| bincode::serde::encode_to_vec(value, CONFIG).unwrap() == encoded)
+        }
+        (None, _) => false,
+    }
+}
+
+impl Matrix {
+    /// Every combination that passes the filters, the last axis changing the fastest.
+    pub fn cases(&self) -> Vec<Settings> {
+        let mut cases = vec![self.base.clone()];
+
+        for axis in &self.axes {
+            cases = cases
+                .into_iter()
+                .flat_map(|case| {
+                    (0..axis.len()).filter_map(move |index| {
+                        let mut case = case.clone();
+                        // Without the field, every value gives the same case.
+                        (axis.visit(&mut case, Some(index)) || index == 0).then_some(case)
+                    })
+                })
+                .collect();
+        }
+
+        let matches = |case: &mut Settings, filter: &Vec<Axis>| filter.iter().all(|axis| axis.visit(case, None));
+
+        cases
+            .into_iter()
+            .filter_map(|mut case| {
+                let excluded = self.exclude.iter().any(|filter| matches(&mut case, filter));
+                let kept = self.only.is_empty() || self.only.iter().any(|filter| matches(&mut case, filter));
+
+                (kept && !excluded).then_some(case)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Scene;
+
+    fn matrix(axes: Vec<Axis>) -> Matrix {
+        let base = Settings {
+            tracing_level: None,
+            headless: true,
+            physics_plugin: PhysicsPlugin::Default,
+            bench_length: 1.0,
+            scene: Scene { camera: (0.0, 0.0, 0.0), num_object: 1, shape: "ball".to_string(), restitution: 0.0, room: Room::Open, ccd: false },
+        };
+
+        Matrix { base, axes, exclude: Vec::new(), only: Vec::new() }
+    }
+
+    fn server() -> PhysicsPlugin {
+        PhysicsPlugin::Server {
+            codec: CodecKind::None,
+            codec_context: CodecContext::default(),
+            address: "localhost".to_string(),
+            transport: TransportKind::default(),
+            checksum: false,
+            generate_scene: false,
+            token: None,
+            partition: None,
+            migration: None,
+            resume: None,
+            record: None,
+            lockstep: None,
+            network: None,
+        }
+    }
+
+    /// The plugin, the codec of the server if any, and the number of objects of every case.
+    fn described(cases: &[Settings]) -> Vec<String> {
+        cases
+            .iter()
+            .map(|case| match &case.physics_plugin {
+                PhysicsPlugin::Default => format!("default {}", case.scene.num_object),
+                PhysicsPlugin::Server { codec, .. } => format!("{codec} {}", case.scene.num_object),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_last_axis_changes_the_fastest() {
+        let matrix = matrix(vec![Axis::NumObject(vec![10, 20]), Axis::Shape(vec!["ball".to_string(), "cube".to_string()])]);
+        let cases = matrix.cases().iter().map(|case| format!("{} {}", case.scene.num_object, case.scene.shape)).collect::<Vec<_>>();
+
+        assert_eq!(cases, ["10 ball", "10 cube", "20 ball", "20 cube"]);
+    }
+
+    #[test]
+    fn axes_of_the_server_leave_the_default_plugin_alone() {
+        let matrix = matrix(vec![
+            Axis::PhysicsPlugin(vec![PhysicsPlugin::Default, server()]),
+            Axis::Codec(vec![CodecKind::None, CodecKind::Lz4]),
+            Axis::NumObject(vec![10, 20]),
+        ]);
+
+        assert_eq!(described(&matrix.cases()), ["default 10", "default 20", "none 10", "none 20", "lz4 10", "lz4 20"]);
+    }
+
+    #[test]
+    fn filters_match_the_cases_with_every_axis() {
+        let mut matrix = matrix(vec![
+            Axis::PhysicsPlugin(vec![PhysicsPlugin::Default, server()]),
+            Axis::Codec(vec![CodecKind::None, CodecKind::Lz4]),
+            Axis::NumObject(vec![10, 20]),
+        ]);
+        matrix.exclude = vec![vec![Axis::Codec(vec![CodecKind::Lz4]), Axis::NumObject(vec![20])]];
+
+        assert_eq!(described(&matrix.cases()), ["default 10", "default 20", "none 10", "none 20", "lz4 10"]);
+
+        // The default plugin has no codec, so it matches no filter on one.
+        matrix.only = vec![vec![Axis::Codec(vec![CodecKind::None])], vec![Axis::NumObject(vec![10])]];
+
+        assert_eq!(described(&matrix.cases()), ["default 10", "none 10", "none 20", "lz4 10"]);
+    }
+}
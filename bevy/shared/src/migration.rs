@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+use crate::codec::CodecKind;
+use crate::handshake::Rejection;
+use crate::state::SessionState;
+
+/// Port the physics servers usually accept the sessions handed over by other servers on, the
+/// servers only listen on it if configured to.
+pub const MIGRATION_PORT: u16 = 4200;
+/// Codec the state of a session travels between the servers with.
+pub const CODEC: CodecKind = CodecKind::Lz4;
+
+/// Moves the session to another server once the client reaches the frame, so that the handoff
+/// shows up in the bench.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Migration {
+    pub frame: u64,
+    /// Address the client reaches the target server at.
+    pub address: String,
+    /// Address the target server accepts handovers at, the host of the address on
+    /// [`MIGRATION_PORT`] if not given.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+}
+
+impl Migration {
+    pub fn endpoint(&self) -> String {
+        if let Some(endpoint) = &self.endpoint {
+            return endpoint.clone();
+        }
+
+        let host = self.address.rsplit_once(':').map_or(self.address.as_str(), |(host, _)| host);
+
+        format!("{host}:{MIGRATION_PORT}")
+    }
+}
+
+/// Sent by the source server to the target one ahead of the client, answered with a
+/// [`Receipt`].
+#[derive(Deserialize, Serialize)]
+pub struct Handover {
+    /// Token of the source server, the target only takes sessions from the servers sharing its own.
+    pub token: Option<String>,
+    /// Presented by the client in its settings to resume the session.
+    pub ticket: u64,
+    pub state: SessionState,
+}
+
+pub type Receipt = Result<(), Rejection>;
+
+/// Answer of the source server to [`crate::request::Request::Migrate`].
+#[derive(Deserialize, Serialize)]
+pub enum Moved {
+    /// The target server holds the session, the client resumes it there with the ticket.
+    Ready { ticket: u64 },
+    /// The session stays on the source server.
+    Failed(Rejection),
+}
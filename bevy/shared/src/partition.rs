@@ -0,0 +1,291 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy_rapier3d::rapier::prelude::{Collider, Real, RigidBody, Vector};
+use serde::{Deserialize, Serialize};
+
+use crate::{request, response};
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub fn of(&self, translation: &Vector<Real>) -> f32 {
+        translation[*self as usize]
+    }
+}
+
+/// Splits the world into slabs along an axis, each one simulated by a different physics server.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Partition {
+    /// Servers of the regions after the first one, which runs at the address of the plugin.
+    pub servers: Vec<String>,
+    pub axis: Axis,
+    /// Where one region ends and the next one starts, ascending, one for every server above.
+    pub bounds: Vec<f32>,
+    /// Bodies closer than this to a boundary are mirrored into the neighbouring region as ghosts.
+    pub margin: f32,
+    /// Region of the server the settings are sent to, filled in by the plugin.
+    #[serde(default)]
+    pub index: usize,
+}
+
+impl Partition {
+    pub fn regions(&self) -> usize {
+        self.bounds.len() + 1
+    }
+
+    /// Region the position along the axis falls into.
+    pub fn locate(&self, position: f32) -> usize {
+        self.bounds.partition_point(|bound| *bound <= position)
+    }
+
+    pub fn region(&self, index: usize) -> Region {
+        Region {
+            axis: self.axis,
+            min: index.checked_sub(1).map_or(f32::NEG_INFINITY, |below| self.bounds[below]),
+            max: self.bounds.get(index).copied().unwrap_or(f32::INFINITY),
+            margin: self.margin,
+        }
+    }
+}
+
+/// Slab of the world a server owns the bodies of, the outermost ones are unbounded.
+#[derive(Clone, Copy)]
+pub struct Region {
+    pub axis: Axis,
+    pub min: f32,
+    pub max: f32,
+    pub margin: f32,
+}
+
+impl Region {
+    pub fn contains(&self, body: &RigidBody) -> bool {
+        let position = self.axis.of(body.translation());
+
+        self.min <= position && position < self.max
+    }
+
+    /// Whether a body inside the region is close enough to a neighbour to be mirrored there.
+    pub fn near_edge(&self, body: &RigidBody) -> bool {
+        let position = self.axis.of(body.translation());
+
+        position - self.min < self.margin || self.max - position < self.margin
+    }
+
+    /// Whether a ghost of a body at its position is close enough to the region to collide with it.
+    pub fn reaches(&self, body: &RigidBody) -> bool {
+        let position = self.axis.of(body.translation());
+
+        self.min - self.margin <= position && position < self.max + self.margin
+    }
+}
+
+/// Body that leaves a server, or is mirrored out of it, along with its colliders.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Transfer {
+    pub body: RigidBody,
+    pub colliders: Vec<Collider>,
+}
+
+/// Splits the requests of the plugin among the servers of a partition and merges their
+/// responses back into one, passing the bodies crossing a boundary on to their new owner.
+pub struct Router {
+    partition: Partition,
+    /// Region owning every dynamic body uploaded so far, by entity.
+    owners: HashMap<u64, usize>,
+    /// Entities of the bodies and colliders every region inserts, whose handles are taken from
+    /// the first region, as its transforms are.
+    shared: HashSet<u64>,
+    /// Bodies to hand to every region with the next request.
+    handoffs: Vec<Vec<Transfer>>,
+    ghosts: Vec<Vec<Transfer>>,
+}
+
+impl Router {
+    pub fn new(partition: Partition) -> Self {
+        let regions = partition.regions();
+
+        Router {
+            partition,
+            owners: HashMap::new(),
+            shared: HashSet::new(),
+            handoffs: vec![Vec::new(); regions],
+            ghosts: vec![Vec::new(); regions],
+        }
+    }
+
+    pub fn split(&mut self, request: request::SyncContext) -> Vec<request::SyncContext> {
+        let mut requests: Vec<request::SyncContext> = (0..self.partition.regions())
+            .map(|region| request::SyncContext {
+                rigid_bodies: Vec::new(),
+                colliders: Vec::new(),
+                delta_seconds: request.delta_seconds,
+                frame: request.frame,
+                handoffs: std::mem::take(&mut self.handoffs[region]),
+                ghosts: std::mem::take(&mut self.ghosts[region]),
+            })
+            .collect();
+
+        for body in request.rigid_bodies {
+            // Bodies that do not move are shared by every region, along with their colliders.
+            if !body.is_dynamic() {
+                self.shared.insert(body.user_data as u64);
+                requests.iter_mut().for_each(|request| request.rigid_bodies.push(body.clone()));
+                continue;
+            }
+
+            let region = self.partition.locate(self.partition.axis.of(body.translation()));
+
+            self.owners.insert(body.user_data as u64, region);
+            requests[region].rigid_bodies.push(body);
+        }
+
+        for collider in request.colliders {
+            match self.owners.get(&(collider.user_data as u64)) {
+                Some(region) => requests[*region].colliders.push(collider),
+                None => {
+                    self.shared.insert(collider.user_data as u64);
+                    requests.iter_mut().for_each(|request| request.colliders.push(collider.clone()));
+                }
+            }
+        }
+
+        requests
+    }
+
+    /// Responses are in the order of the regions.
+    pub fn merge(&mut self, responses: Vec<response::SyncContext>) -> response::SyncContext {
+        let mut merged = response::SyncContext::default();
+
+        for (region, response) in responses.into_iter().enumerate() {
+            merged.frame = merged.frame.max(response.frame);
+            let owned = |entity: &u64| region == 0 || !self.shared.contains(entity);
+            merged.rigid_body_handles.extend(response.rigid_body_handles.into_iter().filter(|(entity, _)| owned(entity)));
+            merged.collider_handles.extend(response.collider_handles.into_iter().filter(|(entity, _)| owned(entity)));
+            merged.transforms.extend(response.transforms);
+
+            for transfer in response.handoffs {
+                let owner = self.partition.locate(self.partition.axis.of(transfer.body.translation()));
+
+                self.owners.insert(transfer.body.user_data as u64, owner);
+                self.handoffs[owner].push(transfer);
+            }
+
+            for ghost in response.ghosts {
+                for (neighbour, ghosts) in self.ghosts.iter_mut().enumerate() {
+                    if neighbour != region && self.partition.region(neighbour).reaches(&ghost.body) {
+                        ghosts.push(ghost.clone());
+                    }
+                }
+            }
+        }
+
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_rapier3d::rapier::prelude::{ColliderBuilder, ColliderHandle, RigidBodyBuilder, RigidBodyHandle, Vector};
+
+    use super::*;
+
+    fn partition(bounds: Vec<f32>) -> Partition {
+        Partition { servers: vec![String::new(); bounds.len()], axis: Axis::X, bounds, margin: 1.0, index: 0 }
+    }
+
+    fn dynamic(entity: u64, x: f32) -> RigidBody {
+        RigidBodyBuilder::dynamic().translation(Vector::new(x, 0.0, 0.0)).user_data(entity as u128).build()
+    }
+
+    fn collider(entity: u64) -> ColliderBuilder {
+        ColliderBuilder::ball(0.5).user_data(entity as u128)
+    }
+
+    fn request(rigid_bodies: Vec<RigidBody>, colliders: Vec<ColliderBuilder>) -> request::SyncContext {
+        request::SyncContext { rigid_bodies, colliders, delta_seconds: 0.016, frame: None, handoffs: Vec::new(), ghosts: Vec::new() }
+    }
+
+    fn entities(request: &request::SyncContext) -> (Vec<u64>, Vec<u64>) {
+        (
+            request.rigid_bodies.iter().map(|body| body.user_data as u64).collect(),
+            request.colliders.iter().map(|collider| collider.user_data as u64).collect(),
+        )
+    }
+
+    fn transfer(entity: u64, x: f32) -> Transfer {
+        Transfer { body: dynamic(entity, x), colliders: vec![collider(entity).build()] }
+    }
+
+    #[test]
+    fn dynamic_bodies_go_to_their_region_and_static_ones_to_all() {
+        let mut router = Router::new(partition(vec![0.0]));
+        let ground = RigidBodyBuilder::fixed().user_data(3).build();
+
+        let requests = router.split(request(
+            vec![dynamic(1, -5.0), dynamic(2, 5.0), ground],
+            vec![collider(1), collider(2), collider(3)],
+        ));
+
+        assert_eq!(entities(&requests[0]), (vec![1, 3], vec![1, 3]));
+        assert_eq!(entities(&requests[1]), (vec![2, 3], vec![2, 3]));
+    }
+
+    #[test]
+    fn handoffs_go_to_the_region_the_body_moved_into() {
+        let mut router = Router::new(partition(vec![0.0, 10.0]));
+        router.split(request(vec![dynamic(1, -5.0)], vec![]));
+
+        let handed_off = response::SyncContext { handoffs: vec![transfer(1, 12.0)], ..Default::default() };
+        router.merge(vec![handed_off, Default::default(), Default::default()]);
+
+        // The colliders of the body follow it to its new owner too.
+        let requests = router.split(request(vec![], vec![collider(1)]));
+        let handoffs = requests.iter().map(|request| request.handoffs.len()).collect::<Vec<_>>();
+
+        assert_eq!(handoffs, [0, 0, 1]);
+        assert_eq!(entities(&requests[2]).1, [1]);
+    }
+
+    #[test]
+    fn ghosts_go_to_the_neighbours_they_reach() {
+        let mut router = Router::new(partition(vec![0.0, 10.0]));
+
+        let near_lower_edge = response::SyncContext { ghosts: vec![transfer(1, 0.5)], ..Default::default() };
+        let near_both_edges = response::SyncContext { ghosts: vec![transfer(2, 9.5)], ..Default::default() };
+        router.merge(vec![Default::default(), near_lower_edge, Default::default()]);
+        router.merge(vec![Default::default(), near_both_edges, Default::default()]);
+
+        let requests = router.split(request(vec![], vec![]));
+        let ghosts = requests
+            .iter()
+            .map(|request| request.ghosts.iter().map(|ghost| ghost.body.user_data as u64).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ghosts, [vec![1], vec![], vec![2]]);
+    }
+
+    #[test]
+    fn static_handles_come_from_the_first_region() {
+        let mut router = Router::new(partition(vec![0.0]));
+        router.split(request(vec![dynamic(1, -5.0), dynamic(2, 5.0), RigidBodyBuilder::fixed().user_data(3).build()], vec![collider(3)]));
+
+        let response = |region: u32, dynamic: u64| response::SyncContext {
+            rigid_body_handles: vec![(dynamic, RigidBodyHandle::from_raw_parts(0, region)), (3, RigidBodyHandle::from_raw_parts(1, region))],
+            collider_handles: vec![(3, ColliderHandle::from_raw_parts(0, region))],
+            ..Default::default()
+        };
+
+        let merged = router.merge(vec![response(0, 1), response(1, 2)]);
+
+        assert_eq!(
+            merged.rigid_body_handles,
+            [(1, RigidBodyHandle::from_raw_parts(0, 0)), (3, RigidBodyHandle::from_raw_parts(1, 0)), (2, RigidBodyHandle::from_raw_parts(0, 1))],
+        );
+        assert_eq!(merged.collider_handles, [(3, ColliderHandle::from_raw_parts(0, 0))]);
+    }
+}
@@ -0,0 +1,164 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use bincode::error::DecodeError;
+use serde::{Deserialize, Serialize};
+
+use crate::request::Request;
+use crate::response::Response;
+use crate::settings::Settings;
+use crate::CONFIG;
+
+#[derive(Deserialize)]
+pub enum Message {
+    Request(Request),
+    Response(Response),
+}
+
+/// Message exchanged with the servers, and when, counting from the start of the recording.
+#[derive(Deserialize)]
+pub struct Entry {
+    pub at: Duration,
+    pub message: Message,
+}
+
+// Written by reference so that nothing is cloned for the recording, read back as an [`Entry`].
+#[derive(Serialize)]
+enum MessageRef<'a> {
+    Request(&'a Request),
+    Response(&'a Response),
+}
+
+#[derive(Serialize)]
+struct EntryRef<'a> {
+    at: Duration,
+    message: MessageRef<'a>,
+}
+
+/// Writes the traffic of a session into a file, the settings of the session without the token
+/// followed by its messages. Every message reaches the file as soon as it is recorded, so that a
+/// crash loses at most the one being written.
+pub struct Recorder {
+    file: BufWriter<File>,
+    started: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, settings: &Settings) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        bincode::serde::encode_into_std_write(settings.redacted(), &mut file, CONFIG).map_err(io::Error::other)?;
+        file.flush()?;
+
+        Ok(Recorder { file, started: Instant::now() })
+    }
+
+    pub fn request(&mut self, request: &Request) -> io::Result<()> {
+        self.write(MessageRef::Request(request))
+    }
+
+    pub fn response(&mut self, response: &Response) -> io::Result<()> {
+        self.write(MessageRef::Response(response))
+    }
+
+    fn write(&mut self, message: MessageRef) -> io::Result<()> {
+        let entry = EntryRef { at: self.started.elapsed(), message };
+        bincode::serde::encode_into_std_write(&entry, &mut self.file, CONFIG).map_err(io::Error::other)?;
+
+        self.file.flush()
+    }
+}
+
+/// Reads back a file written by a [`Recorder`], yielding its messages in the order they were
+/// exchanged. A recording cut short in the middle of a message ends with an error of the kind
+/// [`ErrorKind::UnexpectedEof`].
+pub struct Recording {
+    pub settings: Settings,
+    file: BufReader<File>,
+}
+
+impl Recording {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let settings = decode(&mut file)?;
+
+        Ok(Recording { settings, file })
+    }
+}
+
+impl Iterator for Recording {
+    type Item = io::Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.file.fill_buf() {
+            Ok([]) => None,
+            Ok(_) => Some(decode(&mut self.file)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+fn decode<T: serde::de::DeserializeOwned>(file: &mut BufReader<File>) -> io::Result<T> {
+    bincode::serde::decode_from_std_read(file, CONFIG).map_err(|e| match e {
+        DecodeError::UnexpectedEnd { .. } => io::Error::new(ErrorKind::UnexpectedEof, e),
+        DecodeError::Io { inner, .. } if inner.kind() == ErrorKind::UnexpectedEof => inner,
+        e => io::Error::new(ErrorKind::InvalidData, e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::response;
+    use crate::settings::{PhysicsPlugin, Room, Scene};
+
+    fn recorded(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("bevy-edge-{}-{name}.recording", std::process::id()));
+        let settings = Settings {
+            tracing_level: None,
+            headless: true,
+            physics_plugin: PhysicsPlugin::Default,
+            bench_length: 1.0,
+            scene: Scene { camera: (0.0, 0.0, 0.0), num_object: 7, shape: "ball".to_string(), restitution: 0.0, room: Room::Open, ccd: false },
+        };
+
+        let mut recorder = Recorder::create(&path, &settings).unwrap();
+        recorder.request(&Request::Snapshot).unwrap();
+        recorder.response(&Response::SyncContext(response::SyncContext { frame: 3, ..Default::default() })).unwrap();
+        recorder.request(&Request::Shutdown).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn messages_are_read_back_in_order() {
+        let path = recorded("round-trip");
+        let mut recording = Recording::open(&path).unwrap();
+
+        assert_eq!(recording.settings.scene.num_object, 7);
+        assert!(matches!(recording.next(), Some(Ok(Entry { message: Message::Request(Request::Snapshot), .. }))));
+        assert!(matches!(
+            recording.next(),
+            Some(Ok(Entry { message: Message::Response(Response::SyncContext(response::SyncContext { frame: 3, .. })), .. })),
+        ));
+        assert!(matches!(recording.next(), Some(Ok(Entry { message: Message::Request(Request::Shutdown), .. }))));
+        assert!(recording.next().is_none());
+    }
+
+    #[test]
+    fn a_message_cut_short_is_an_unexpected_eof() {
+        let path = recorded("truncated");
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(file.metadata().unwrap().len() - 1).unwrap();
+
+        let entries = Recording::open(&path).unwrap().collect::<Vec<_>>();
+
+        assert_eq!(entries.len(), 3);
+        assert!(entries[..2].iter().all(Result::is_ok));
+        assert_eq!(entries[2].as_ref().err().map(io::Error::kind), Some(ErrorKind::UnexpectedEof));
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::codec::NoneCodec;
+use crate::discovery::Announcement;
+use crate::frame::{self, FrameReader, MessageKind};
+use crate::handshake;
+use crate::transport::{self, TransportKind};
+
+/// Port the broker accepts the registrations of the physics servers on.
+pub const REGISTRY_PORT: u16 = 4100;
+/// How often a registered server reports its load.
+pub const HEARTBEAT: Duration = Duration::from_secs(1);
+/// A server that does not report for this long is considered gone.
+const EXPIRY: Duration = Duration::from_secs(3);
+
+/// Sent by a physics server every [`HEARTBEAT`] over its connection to the broker.
+#[derive(Deserialize, Serialize)]
+pub struct Registration {
+    /// Host the clients reach the server at, the address it connects to the broker from if not given.
+    pub host: Option<String>,
+    /// The broker only takes the servers that present its token.
+    pub token: Option<String>,
+    pub announcement: Announcement,
+}
+
+/// Keeps the server registered with the broker, reconnecting whenever the broker goes away.
+pub fn register<F>(broker: String, host: Option<String>, token: Option<String>, announce: F)
+where
+    F: Fn() -> Announcement + Send + 'static,
+{
+    std::thread::spawn(move || loop {
+        let mut link = match transport::connect(&TransportKind::Tcp, &broker) {
+            Ok(link) => link,
+            Err(e) => {
+                warn!("failed to connect to the broker at {broker}, {e}");
+                std::thread::sleep(HEARTBEAT);
+                continue;
+            }
+        };
+
+        debug!("registered with the broker at {broker}");
+
+        loop {
+            let registration = Registration { host: host.clone(), token: token.clone(), announcement: announce() };
+
+            if let Err(e) = link.send(MessageKind::Registry, &mut NoneCodec, &registration) {
+                warn!("lost the connection to the broker at {broker}, {e}");
+                break;
+            }
+
+            std::thread::sleep(HEARTBEAT);
+        }
+    });
+}
+
+/// Physics server known to the broker.
+#[derive(Clone)]
+pub struct Registered {
+    pub host: String,
+    pub announcement: Announcement,
+    /// Clients sent to the server since its last report, which does not count them yet.
+    pub pending: u32,
+}
+
+impl Registered {
+    fn load(&self) -> f32 {
+        (self.announcement.active_sessions + self.pending) as f32 / self.announcement.max_sessions.max(1) as f32
+    }
+
+    fn is_full(&self) -> bool {
+        self.announcement.active_sessions + self.pending >= self.announcement.max_sessions
+    }
+
+    /// Address of the server for a client connecting over the transport.
+    pub fn address(&self, transport: &TransportKind) -> String {
+        let port = self.announcement.port(transport);
+
+        match self.host.contains(':') {
+            true => format!("[{}]:{}", self.host, port),
+            false => format!("{}:{}", self.host, port),
+        }
+    }
+}
+
+/// Servers registered with the broker, keyed by the address they registered from.
+#[derive(Clone, Default)]
+pub struct Registry {
+    servers: Arc<Mutex<HashMap<SocketAddr, Registered>>>,
+    token: Option<Arc<str>>,
+}
+
+impl Registry {
+    /// Accepts registrations that present the token on the address in the background.
+    pub fn bind(address: &str, token: Option<&str>) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let registry = Registry { servers: Arc::default(), token: token.map(Arc::from) };
+
+        let accepting = registry.clone();
+        std::thread::spawn(move || loop {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    let registry = accepting.clone();
+                    std::thread::spawn(move || registry.follow(stream, peer));
+                }
+                Err(e) => error!("failed to accept a registration, {e}"),
+            }
+        });
+
+        Ok(registry)
+    }
+
+    /// Reads the reports of a server until it stops sending them.
+    fn follow(&self, stream: TcpStream, peer: SocketAddr) {
+        if let Err(e) = stream.set_read_timeout(Some(EXPIRY)) {
+            error!("failed to set the expiry of {peer}, {e}");
+            return;
+        }
+
+        let mut reader = FrameReader::new();
+
+        let error = loop {
+            let registration = reader.read_frame(&stream)
+                .and_then(|frame| frame::decode::<Registration>(&mut NoneCodec, &frame, MessageKind::Registry));
+
+            let registration = match registration {
+                Ok((registration, _)) => registration,
+                Err(e) => break e.to_string(),
+            };
+
+            if let Err(rejection) = handshake::authenticate(self.token.as_deref(), registration.token.as_deref()) {
+                warn!("dropping the registration of {peer}, {rejection}");
+                break rejection.to_string();
+            }
+
+            let mut servers = self.servers.lock().unwrap();
+            if !servers.contains_key(&peer) {
+                debug!("physics server at {peer} is registered");
+            }
+
+            servers.insert(peer, Registered {
+                host: registration.host.unwrap_or_else(|| peer.ip().to_string()),
+                announcement: registration.announcement,
+                pending: 0,
+            });
+        };
+
+        debug!("physics server at {peer} is unregistered, {error}");
+        self.servers.lock().unwrap().remove(&peer);
+    }
+
+    pub fn servers(&self) -> Vec<Registered> {
+        self.servers.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Picks the least loaded server that can take another session and counts the client
+    /// towards its load until the server reports again.
+    pub fn route(&self) -> Option<Registered> {
+        let mut servers = self.servers.lock().unwrap();
+
+        let server = servers.values_mut()
+            .filter(|server| !server.is_full())
+            .min_by(|a, b| a.load().total_cmp(&b.load()))?;
+        server.pending += 1;
+
+        Some(server.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::discovery::Service;
+
+    fn announcement(active_sessions: u32) -> Announcement {
+        Announcement {
+            service: Service::Physics,
+            port: 4001,
+            quic_port: Some(4500),
+            tls: false,
+            token_required: true,
+            active_sessions,
+            max_sessions: 4,
+        }
+    }
+
+    fn wait_for(registry: &Registry, servers: usize) {
+        let started = Instant::now();
+        while registry.servers().len() < servers {
+            assert!(started.elapsed() < Duration::from_secs(5), "servers did not register");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn clients_go_to_the_least_loaded_server_with_the_token() {
+        let address = "127.0.0.1:47131";
+        let registry = Registry::bind(address, Some("secret")).unwrap();
+
+        register(address.to_string(), Some("busy".to_string()), Some("secret".to_string()), || announcement(3));
+        register(address.to_string(), Some("idle".to_string()), Some("secret".to_string()), || announcement(1));
+        register(address.to_string(), Some("intruder".to_string()), Some("guess".to_string()), || announcement(0));
+        wait_for(&registry, 2);
+        std::thread::sleep(HEARTBEAT);
+
+        let mut hosts = registry.servers().into_iter().map(|server| server.host).collect::<Vec<_>>();
+        hosts.sort();
+        assert_eq!(hosts, ["busy", "idle"]);
+
+        // The client sent to the idle server counts towards its load, it still has the lower one.
+        for _ in 0..2 {
+            let server = registry.route().unwrap();
+            assert_eq!(server.host, "idle");
+            assert_eq!(server.address(&TransportKind::Tcp), "idle:4001");
+            assert_eq!(server.address(&TransportKind::Quic { certificate: None, insecure: false }), "idle:4500");
+        }
+    }
+}
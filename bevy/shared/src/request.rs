@@ -1,15 +1,28 @@
 use bevy_rapier3d::rapier::{dynamics::RigidBody, prelude::ColliderBuilder};
 use serde::{Deserialize, Serialize};
 
+use crate::partition::Transfer;
+
 #[derive(Deserialize, Serialize)]
 pub struct SyncContext {
     pub rigid_bodies: Vec<RigidBody>,
     pub colliders: Vec<ColliderBuilder>,
     pub delta_seconds: f32,
+    /// Frame the client reached, the server steps up to it instead of by the delta in lockstep.
+    pub frame: Option<u64>,
+    /// Bodies a neighbouring region handed off to this one, only when the world is partitioned.
+    pub handoffs: Vec<Transfer>,
+    /// Bodies of the neighbouring regions close enough to collide with the ones of this region.
+    pub ghosts: Vec<Transfer>,
 }
 
 #[derive(Deserialize, Serialize)]
 pub enum Request {
     SyncContext(SyncContext),
+    /// Hands the session over to the server accepting handovers at the endpoint, answered with
+    /// [`crate::migration::Moved`].
+    Migrate { endpoint: String },
+    /// Saves the state of the session on the server, answered with nothing.
+    Snapshot,
     Shutdown,
 }
@@ -2,21 +2,73 @@ use bevy_rapier3d::rapier::prelude::{ColliderHandle, RigidBodyHandle};
 use bevy_transform::prelude::Transform;
 use serde::{Deserialize, Serialize};
 
+use crate::partition::Transfer;
+use crate::CONFIG;
+
+/// Transforms per datagram of a snapshot, each one takes at most 49 bytes once encoded.
+const TRANSFORMS_PER_CHUNK: usize = 24;
+
+/// Where the physics time of a frame goes, in microseconds. The phases of the pipeline are only
+/// timed by servers built with the `profiler` feature of the physics crate, zero otherwise.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+pub struct Phases {
+    /// Inserting the bodies and colliders of the request into the world.
+    pub insert: u32,
+    pub broad_phase: u32,
+    pub narrow_phase: u32,
+    pub island_construction: u32,
+    pub solver: u32,
+    pub ccd: u32,
+    /// Collecting the transforms of the bodies into the response.
+    pub collect: u32,
+}
+
 #[derive(Default, Deserialize, Serialize)]
 pub struct Log {
     pub physics_time: u32,
+    pub phases: Phases,
     pub compress_time: u32,
     pub decompress_time: u32,
+    /// When the request arrived, before it was decompressed, in microseconds on the server's clock.
+    pub received_at: u64,
+    /// When the response left, in microseconds on the server's clock.
+    pub responded_at: u64,
 }
 
 #[derive(Default, Deserialize, Serialize)]
 pub struct SyncContext {
+    /// Frame the server simulated up to, the transforms are the state of the world at its end.
+    pub frame: u64,
     pub rigid_body_handles: Vec<(u64, RigidBodyHandle)>,
     pub collider_handles: Vec<(u64, ColliderHandle)>,
     pub transforms: Vec<(u64, Transform)>,
+    /// Bodies that left the region of the server, for the plugin to pass on to their new owner.
+    pub handoffs: Vec<Transfer>,
+    /// Bodies close to the edges of the region of the server, mirrored into its neighbours.
+    pub ghosts: Vec<Transfer>,
 }
 
 #[derive(Deserialize, Serialize)]
 pub enum Response {
     SyncContext(SyncContext),
 }
+
+/// Splits the transforms into independently decodable chunks, so that a lost datagram only
+/// delays the bodies it carries.
+pub fn encode_snapshot(transforms: &[(u64, Transform)]) -> Vec<Vec<u8>> {
+    transforms
+        .chunks(TRANSFORMS_PER_CHUNK)
+        .map(|chunk| bincode::serde::encode_to_vec(chunk, CONFIG).unwrap())
+        .collect()
+}
+
+pub fn decode_snapshot(chunks: Vec<Vec<u8>>) -> Result<Vec<(u64, Transform)>, bincode::error::DecodeError> {
+    let mut transforms = Vec::new();
+
+    for chunk in chunks {
+        let (decoded, _): (Vec<(u64, Transform)>, _) = bincode::serde::decode_from_slice(&chunk, CONFIG)?;
+        transforms.extend(decoded);
+    }
+
+    Ok(transforms)
+}
@@ -0,0 +1,182 @@
+use bevy_rapier3d::rapier::{
+    math::Vector,
+    prelude::{CoefficientCombineRule, ColliderBuilder, Isometry, RigidBody, RigidBodyBuilder, SharedShape},
+};
+
+use crate::settings::{Room, Scene};
+
+pub const ROOM_LENGTH: f32 = 15.0;
+pub const WALL_THICKNESS: f32 = 1.0;
+
+#[derive(Clone)]
+pub enum Shape {
+    Ball(f32),
+    Capsule { half_height: f32, radius: f32 },
+    Cuboid(f32, f32, f32),
+    /// Three cuboids with the given half extent, two of them stacked on the sides of the first one.
+    Complex(f32),
+}
+
+impl Shape {
+    pub fn shared_shape(&self) -> SharedShape {
+        match *self {
+            Shape::Ball(radius) => SharedShape::ball(radius),
+            Shape::Capsule { half_height, radius } => SharedShape::capsule_y(half_height, radius),
+            Shape::Cuboid(hx, hy, hz) => SharedShape::cuboid(hx, hy, hz),
+            Shape::Complex(rad) => SharedShape::compound(vec![
+                (Isometry::identity(), SharedShape::cuboid(rad, rad, rad)),
+                (Isometry::translation(rad, rad, 0.0), SharedShape::cuboid(rad, rad, rad)),
+                (Isometry::translation(-rad, rad, 0.0), SharedShape::cuboid(rad, rad, rad)),
+            ]),
+        }
+    }
+}
+
+/// A single body of the scene, described independently of bevy so that both the client and the
+/// server can build it.
+#[derive(Clone)]
+pub struct Body {
+    pub shape: Shape,
+    pub dynamic: bool,
+    pub translation: (f32, f32, f32),
+    pub linvel: (f32, f32, f32),
+    pub density: Option<f32>,
+    pub restitution: Option<f32>,
+    pub friction: Option<f32>,
+    pub ccd: bool,
+    pub color: (f32, f32, f32),
+}
+
+impl Body {
+    fn fixed(shape: Shape, translation: (f32, f32, f32)) -> Self {
+        Body {
+            shape,
+            dynamic: false,
+            translation,
+            linvel: (0.0, 0.0, 0.0),
+            density: None,
+            restitution: None,
+            friction: None,
+            ccd: false,
+            color: (0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn rigid_body(&self, user_data: u128) -> RigidBody {
+        let builder = if self.dynamic { RigidBodyBuilder::dynamic() } else { RigidBodyBuilder::fixed() };
+
+        builder
+            .translation(Vector::new(self.translation.0, self.translation.1, self.translation.2))
+            .linvel(Vector::new(self.linvel.0, self.linvel.1, self.linvel.2))
+            .ccd_enabled(self.ccd)
+            .user_data(user_data)
+            .build()
+    }
+
+    pub fn collider(&self, user_data: u128) -> ColliderBuilder {
+        let mut builder = ColliderBuilder::new(self.shape.shared_shape());
+
+        if let Some(density) = self.density {
+            builder = builder.density(density);
+        }
+
+        if let Some(restitution) = self.restitution {
+            builder = builder
+                .restitution(restitution)
+                .restitution_combine_rule(CoefficientCombineRule::Max);
+        }
+
+        if let Some(friction) = self.friction {
+            builder = builder
+                .friction(friction)
+                .friction_combine_rule(CoefficientCombineRule::Max);
+        }
+
+        builder.user_data(user_data)
+    }
+}
+
+/// Returns the bodies of the scene in a deterministic order: the walls, the stack of objects and
+/// lastly the ball thrown at the stack.
+pub fn bodies(scene: &Scene) -> Vec<Body> {
+    let length = ROOM_LENGTH;
+    let thickness = WALL_THICKNESS;
+
+    let walls = if let Room::Closed = scene.room {
+        vec![
+            ((length * 2.0, thickness, length * 2.0), (0.0, -thickness, 0.0)),
+            ((length * 2.0, thickness, length * 2.0), (0.0, length * 10.0 - thickness, 0.0)),
+            ((thickness, length * 10.0, length * 2.0), (-length, 0.0, 0.0)),
+            ((thickness, length * 10.0, length * 2.0), (length, 0.0, 0.0)),
+            ((length, length * 10.0, thickness), (0.0, 0.0, length * 2.0)),
+            ((length, length * 10.0, thickness), (0.0, 0.0, -length * 2.0)),
+        ]
+    } else {
+        vec![
+            ((length * 2.0, thickness, length * 2.0), (0.0, -thickness, 0.0)),
+        ]
+    };
+
+    let mut bodies: Vec<Body> = walls
+        .into_iter()
+        .map(|(extents, translation)| Body::fixed(Shape::Cuboid(extents.0, extents.1, extents.2), translation))
+        .collect();
+
+    let num = 10;
+    let rad = 0.4;
+
+    let shape = match scene.shape.as_str() {
+        "cuboid" => Shape::Cuboid(rad, rad, rad),
+        "capsule" => Shape::Capsule { half_height: rad, radius: rad },
+        "complex" => Shape::Complex(rad / 2.0),
+        _ => Shape::Ball(rad),
+    };
+
+    let shift = rad * 2.0 + 0.005;
+    let centerx = shift * (num as f32) / 2.0;
+    let centery = shift / 2.0;
+    let centerz = shift * (num as f32) / 2.0;
+
+    let height = scene.num_object / num / num;
+    let density = 0.477;
+
+    for i in 0..num {
+        for j in 0usize..height {
+            for k in 0..num {
+                bodies.push(Body {
+                    shape: shape.clone(),
+                    dynamic: true,
+                    translation: (
+                        i as f32 * shift - centerx,
+                        j as f32 * shift + centery,
+                        k as f32 * shift - centerz,
+                    ),
+                    linvel: (0.0, 0.0, 0.0),
+                    density: Some(density),
+                    restitution: Some(scene.restitution),
+                    friction: Some(0.0),
+                    ccd: scene.ccd,
+                    color: (
+                        (i % 3) as f32 * 0.33,
+                        (j % 3) as f32 * 0.33,
+                        (k % 3) as f32 * 0.33,
+                    ),
+                });
+            }
+        }
+    }
+
+    bodies.push(Body {
+        shape: Shape::Ball(1.0),
+        dynamic: true,
+        translation: (0.0, 2.0, length - 2.0),
+        linvel: (0.0, 0.0, -30.0),
+        density: Some(1.0),
+        restitution: None,
+        friction: None,
+        ccd: false,
+        color: (0.0, 0.0, 0.0),
+    });
+
+    bodies
+}
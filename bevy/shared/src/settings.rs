@@ -1,26 +1,123 @@
 use std::fmt::Display;
 
 use bevy_ecs::system::Resource;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::codec::{CodecContext, CodecKind};
+use crate::migration::Migration;
+use crate::partition::Partition;
+use crate::transport::{NetworkEmulation, TransportKind};
+
+#[allow(clippy::large_enum_variant)] // Read once at startup.
 #[derive(Clone, Deserialize, Serialize)]
 pub enum PhysicsPlugin {
     Default,
     Server {
-        compress: Option<u32>,
+        /// Settings written before the codecs could be picked give the level of deflate as `compress`.
+        #[serde(alias = "compress", deserialize_with = "codec")]
+        codec: CodecKind,
+        #[serde(default)]
+        codec_context: CodecContext,
         address: String,
+        #[serde(default)]
+        transport: TransportKind,
+        /// Append a CRC32 of the payload to every frame.
+        #[serde(default)]
+        checksum: bool,
+        /// Let the server build the scene from [`Scene`] instead of uploading every body.
+        #[serde(default)]
+        generate_scene: bool,
+        /// Pre-shared token the server may require before accepting the session.
+        #[serde(default)]
+        token: Option<String>,
+        /// Split the world among several servers instead of simulating it on one.
+        #[serde(default)]
+        partition: Option<Partition>,
+        #[serde(default)]
+        migration: Option<Migration>,
+        /// Ticket of a session moved to the server, filled in by the plugin.
+        #[serde(default)]
+        resume: Option<u64>,
+        /// File to record the requests and responses of the session into, for the replay tool.
+        #[serde(default)]
+        record: Option<String>,
+        #[serde(default)]
+        lockstep: Option<Lockstep>,
+        /// Conditions of the link to emulate, by the plugin on the responses and by the server on the requests.
+        #[serde(default)]
+        network: Option<NetworkEmulation>,
     },
 }
 
+/// The codecs, or the level of deflate as `compress` gave it, `None` for no compression.
+#[derive(Deserialize)]
+enum Codec {
+    // The codecs in the order of [`CodecKind`], so that the binary encoding stays the same.
+    None,
+    Deflate(u32),
+    Zstd(i32),
+    Lz4,
+    Some(u32),
+}
+
+fn codec<'de, D: Deserializer<'de>>(deserializer: D) -> Result<CodecKind, D::Error> {
+    let codec = match Codec::deserialize(deserializer)? {
+        Codec::None => CodecKind::None,
+        Codec::Deflate(level) | Codec::Some(level) => CodecKind::Deflate(level),
+        Codec::Zstd(level) => CodecKind::Zstd(level),
+        Codec::Lz4 => CodecKind::Lz4,
+    };
+
+    Ok(codec)
+}
+
+/// Steps the world in fixed increments up to the frame the client reached, one for every frame of
+/// the client instead of by the time its frames take, so that every run simulates the same frames.
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct Lockstep {
+    /// Length of a step in seconds.
+    pub timestep: f32,
+    /// Steps the server takes at most for one request of a client that fell behind, the rest
+    /// follow with the next requests.
+    pub max_substeps: u32,
+}
+
 impl Display for PhysicsPlugin {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             PhysicsPlugin::Default => f.write_str("default"),
-            PhysicsPlugin::Server { compress: None, .. } => f.write_str("server_none"),
-            PhysicsPlugin::Server {
-                compress: Some(compress),
-                ..
-            } => f.write_str(format!("server_{}", compress).as_str()),
+            PhysicsPlugin::Server { codec, codec_context: CodecContext::Reset, .. } => f.write_fmt(format_args!("server_{}", codec)),
+            PhysicsPlugin::Server { codec, codec_context, .. } => f.write_fmt(format_args!("server_{}_{}", codec, codec_context)),
+        }?;
+
+        match self {
+            PhysicsPlugin::Server { transport: TransportKind::Tcp, .. } | PhysicsPlugin::Default => Ok(()),
+            PhysicsPlugin::Server { transport, .. } => f.write_fmt(format_args!("_{}", transport)),
+        }?;
+
+        match self {
+            PhysicsPlugin::Server { generate_scene: true, .. } => f.write_str("_gen"),
+            _ => Ok(()),
+        }?;
+
+        match self {
+            PhysicsPlugin::Server { partition: Some(partition), .. } => f.write_fmt(format_args!("_split{}", partition.regions())),
+            _ => Ok(()),
+        }?;
+
+        match self {
+            PhysicsPlugin::Server { migration: Some(migration), .. } => f.write_fmt(format_args!("_mig{}", migration.frame)),
+            _ => Ok(()),
+        }?;
+
+        match self {
+            PhysicsPlugin::Server { lockstep: Some(_), .. } => f.write_str("_lock"),
+            _ => Ok(()),
+        }?;
+
+        match self {
+            PhysicsPlugin::Server { network: Some(network), .. } => f.write_fmt(format_args!("_net_{}", network)),
+            _ => Ok(()),
         }
     }
 }
@@ -50,6 +147,18 @@ pub struct Settings {
     pub scene: Scene,
 }
 
+impl Settings {
+    /// The settings without the token, for the files they end up in.
+    pub fn redacted(&self) -> Settings {
+        let mut settings = self.clone();
+        if let PhysicsPlugin::Server { token, .. } = &mut settings.physics_plugin {
+            *token = None;
+        }
+
+        settings
+    }
+}
+
 impl Display for Settings {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("{}_{}_{}",  self.physics_plugin, self.scene.num_object, self.scene.shape))
@@ -0,0 +1,42 @@
+use bevy_ecs::prelude::Entity;
+use bevy_rapier3d::prelude::RapierContext;
+use bevy_rapier3d::rapier::prelude::{ColliderHandle, ImpulseJointHandle, MultibodyJointHandle, RigidBodyHandle};
+use serde::{Deserialize, Serialize};
+
+/// Everything a session needs to continue simulating from the frame it reached, on another
+/// server or in another process.
+#[derive(Deserialize, Serialize)]
+pub struct SessionState {
+    pub frame: u64,
+    pub context: RapierContext,
+    // The context leaves its maps from entities out when it is serialized.
+    entity2body: Vec<(u64, RigidBodyHandle)>,
+    entity2collider: Vec<(u64, ColliderHandle)>,
+    entity2impulse_joint: Vec<(u64, ImpulseJointHandle)>,
+    entity2multibody_joint: Vec<(u64, MultibodyJointHandle)>,
+}
+
+impl SessionState {
+    pub fn new(frame: u64, context: RapierContext) -> Self {
+        SessionState {
+            frame,
+            entity2body: context.entity2body.iter().map(|(entity, handle)| (entity.to_bits(), *handle)).collect(),
+            entity2collider: context.entity2collider.iter().map(|(entity, handle)| (entity.to_bits(), *handle)).collect(),
+            entity2impulse_joint: context.entity2impulse_joint.iter().map(|(entity, handle)| (entity.to_bits(), *handle)).collect(),
+            entity2multibody_joint: context.entity2multibody_joint.iter().map(|(entity, handle)| (entity.to_bits(), *handle)).collect(),
+            context,
+        }
+    }
+
+    /// The context with its maps from entities restored, and the frame it reached.
+    pub fn into_context(self) -> (u64, RapierContext) {
+        let mut context = self.context;
+
+        context.entity2body = self.entity2body.into_iter().map(|(entity, handle)| (Entity::from_bits(entity), handle)).collect();
+        context.entity2collider = self.entity2collider.into_iter().map(|(entity, handle)| (Entity::from_bits(entity), handle)).collect();
+        context.entity2impulse_joint = self.entity2impulse_joint.into_iter().map(|(entity, handle)| (Entity::from_bits(entity), handle)).collect();
+        context.entity2multibody_joint = self.entity2multibody_joint.into_iter().map(|(entity, handle)| (Entity::from_bits(entity), handle)).collect();
+
+        (self.frame, context)
+    }
+}
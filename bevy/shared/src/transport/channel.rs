@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use super::{Framed, Stream, Transport};
+
+/// One end of an in-memory byte stream, for a client and a server running in the same process.
+struct ChannelStream {
+    tx: Option<Sender<Vec<u8>>>,
+    rx: Receiver<Vec<u8>>,
+    buffer: VecDeque<u8>,
+}
+
+impl Read for ChannelStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.buffer.is_empty() {
+            match self.rx.recv() {
+                Ok(chunk) => self.buffer.extend(chunk),
+                // The other end is gone, which reads as the end of the stream.
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.buffer.len());
+        for (dest, byte) in buf.iter_mut().zip(self.buffer.drain(..len)) {
+            *dest = byte;
+        }
+
+        Ok(len)
+    }
+}
+
+impl Write for ChannelStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let tx = self.tx.as_ref().ok_or(ErrorKind::BrokenPipe)?;
+        tx.send(buf.to_vec()).map_err(|_| io::Error::from(ErrorKind::BrokenPipe))?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for ChannelStream {
+    fn close(&mut self) -> io::Result<()> {
+        self.tx = None;
+
+        Ok(())
+    }
+}
+
+/// Returns two connected transports that need no sockets, the first one for the client.
+pub fn channel() -> (Box<dyn Transport>, Box<dyn Transport>) {
+    let (client_tx, server_rx) = mpsc::channel();
+    let (server_tx, client_rx) = mpsc::channel();
+
+    (
+        Framed::boxed("channel", ChannelStream { tx: Some(client_tx), rx: client_rx, buffer: VecDeque::new() }),
+        Framed::boxed("channel", ChannelStream { tx: Some(server_tx), rx: server_rx, buffer: VecDeque::new() }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::NoneCodec;
+    use crate::frame::{FrameError, MessageKind};
+
+    #[test]
+    fn messages_cross_in_order() {
+        let (mut client, mut server) = channel();
+
+        client.send(MessageKind::Request, &mut NoneCodec, &1u32).unwrap();
+        client.send(MessageKind::Request, &mut NoneCodec, &"two".to_string()).unwrap();
+
+        assert_eq!(server.recv::<u32>(MessageKind::Request, &mut NoneCodec).unwrap().0, 1);
+        assert_eq!(server.recv::<String>(MessageKind::Request, &mut NoneCodec).unwrap().0, "two");
+
+        server.send(MessageKind::Response, &mut NoneCodec, &vec![3u8; 100_000]).unwrap();
+        assert_eq!(client.recv::<Vec<u8>>(MessageKind::Response, &mut NoneCodec).unwrap().0, vec![3u8; 100_000]);
+    }
+
+    #[test]
+    fn close_ends_the_stream_of_the_peer() {
+        let (mut client, mut server) = channel();
+
+        client.send(MessageKind::Request, &mut NoneCodec, &1u32).unwrap();
+        client.close().unwrap();
+
+        assert_eq!(server.recv::<u32>(MessageKind::Request, &mut NoneCodec).unwrap().0, 1);
+        assert!(matches!(server.recv::<u32>(MessageKind::Request, &mut NoneCodec), Err(FrameError::Io(_))));
+        assert!(client.send(MessageKind::Request, &mut NoneCodec, &2u32).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt::Display;
+use std::io;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::frame::{Frame, FrameError, MessageKind, HEADER_SIZE};
+
+use super::{Counters, Transport};
+
+/// A frame read sooner than this after asking for it was already waiting, it arrived along with
+/// the one before it.
+const BUFFERED: Duration = Duration::from_millis(1);
+
+fn default_stall() -> f32 {
+    200.0
+}
+
+/// Conditions of a link, such as a mobile one, that both ends emulate on what they receive.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NetworkEmulation {
+    /// Delay of every frame in one direction, in milliseconds.
+    #[serde(default)]
+    pub latency: f32,
+    /// Up to this much more or less delay for every frame, at random, in milliseconds.
+    #[serde(default)]
+    pub jitter: f32,
+    /// Capacity of the link in kilobits per second, frames queue behind each other beyond it.
+    #[serde(default)]
+    pub bandwidth: Option<u32>,
+    /// Share of the packets lost, from 0 to 1. Chunks of snapshots are dropped, frames are held
+    /// back until they are sent again.
+    #[serde(default)]
+    pub loss: f32,
+    /// How long a lost frame holds the link back, in milliseconds.
+    #[serde(default = "default_stall")]
+    pub stall: f32,
+    /// The same seed loses and delays the same frames in every run.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl Display for NetworkEmulation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("lat{}_jit{}", self.latency, self.jitter))?;
+
+        if let Some(bandwidth) = self.bandwidth {
+            f.write_fmt(format_args!("_bw{}", bandwidth))?;
+        }
+
+        if self.loss > 0.0 {
+            f.write_fmt(format_args!("_loss{}_stall{}", self.loss, self.stall))?;
+        }
+
+        // Only the jitter and the loss are random.
+        if self.jitter > 0.0 || self.loss > 0.0 {
+            f.write_fmt(format_args!("_seed{}", self.seed))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Holds back what the transport receives as the link of the conditions would.
+pub fn emulate(transport: Box<dyn Transport>, conditions: NetworkEmulation) -> Box<dyn Transport> {
+    Box::new(Emulated::new(transport, conditions))
+}
+
+struct Emulated {
+    inner: Box<dyn Transport>,
+    conditions: NetworkEmulation,
+    /// State of a xorshift generator, never 0.
+    random: u64,
+    arrived: Instant,
+    delivered: Instant,
+}
+
+impl Emulated {
+    fn new(inner: Box<dyn Transport>, conditions: NetworkEmulation) -> Self {
+        // Spreads the bits of small seeds, xorshift starts out with small values from them.
+        let mut random = conditions.seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        random = (random ^ (random >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        random = (random ^ (random >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        let now = Instant::now();
+
+        Emulated { inner, conditions, random: (random ^ (random >> 31)) | 1, arrived: now, delivered: now }
+    }
+
+    /// Uniform in [0, 1).
+    fn random(&mut self) -> f32 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+
+        (self.random >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Time the bytes take to go through the link.
+    fn transmission(&self, bytes: usize) -> Duration {
+        match self.conditions.bandwidth {
+            Some(bandwidth) => Duration::from_secs_f64((bytes * 8) as f64 / (bandwidth as f64 * 1000.0)),
+            None => Duration::ZERO,
+        }
+    }
+
+    fn lost(&mut self) -> bool {
+        self.random() < self.conditions.loss
+    }
+
+    /// Delay of the next frame, with the stall if it is lost.
+    fn delay(&mut self) -> Duration {
+        let jitter = (self.random() * 2.0 - 1.0) * self.conditions.jitter;
+        let mut delay = Duration::from_secs_f32((self.conditions.latency + jitter).max(0.0) / 1000.0);
+
+        if self.lost() {
+            delay += Duration::from_secs_f32(self.conditions.stall / 1000.0);
+        }
+
+        delay
+    }
+
+    /// Waits until the bytes would be delivered, in order after everything before them.
+    fn deliver(&mut self, arrived: Instant, bytes: usize, delayed: bool) {
+        let mut delivery = arrived;
+
+        if delayed {
+            delivery += self.delay();
+        }
+
+        self.delivered = delivery.max(self.delivered) + self.transmission(bytes);
+        std::thread::sleep(self.delivered.saturating_duration_since(Instant::now()));
+    }
+}
+
+impl Transport for Emulated {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn set_checksum(&mut self, checksum: bool) {
+        self.inner.set_checksum(checksum);
+    }
+
+    fn send_frame(&mut self, kind: MessageKind, payload: &[u8], raw_length: usize) -> Result<(), FrameError> {
+        self.inner.send_frame(kind, payload, raw_length)
+    }
+
+    fn recv_frame(&mut self) -> Result<Frame, FrameError> {
+        let instant = Instant::now();
+        let frame = self.inner.recv_frame()?;
+
+        let arrived = if instant.elapsed() < BUFFERED { self.arrived } else { Instant::now() };
+        self.arrived = arrived;
+        self.deliver(arrived, HEADER_SIZE + frame.payload.len(), true);
+
+        Ok(frame)
+    }
+
+    fn supports_snapshots(&self) -> bool {
+        self.inner.supports_snapshots()
+    }
+
+    fn send_snapshot(&mut self, chunks: &[Vec<u8>]) -> io::Result<()> {
+        self.inner.send_snapshot(chunks)
+    }
+
+    /// The chunks follow the frame they are sent with, only their size and their loss count.
+    fn recv_snapshot(&mut self, wait: Duration) -> io::Result<Vec<Vec<u8>>> {
+        let mut chunks = self.inner.recv_snapshot(wait)?;
+        chunks.retain(|_| !self.lost());
+
+        let bytes = chunks.iter().map(|chunk| chunk.len()).sum();
+        self.deliver(self.arrived, bytes, false);
+
+        Ok(chunks)
+    }
+
+    fn counters(&self) -> &Counters {
+        self.inner.counters()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.inner.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::channel;
+
+    fn emulated(seed: u64) -> Emulated {
+        let conditions = NetworkEmulation { latency: 50.0, jitter: 20.0, bandwidth: None, loss: 0.2, stall: 200.0, seed };
+
+        Emulated::new(channel().0, conditions)
+    }
+
+    fn pattern(emulated: &mut Emulated) -> (Vec<Duration>, Vec<bool>) {
+        let delays = (0..100).map(|_| emulated.delay()).collect();
+        let losses = (0..100).map(|_| emulated.lost()).collect();
+
+        (delays, losses)
+    }
+
+    #[test]
+    fn the_seed_decides_the_delays_and_losses() {
+        let (delays, losses) = pattern(&mut emulated(7));
+
+        assert_eq!((delays.clone(), losses.clone()), pattern(&mut emulated(7)));
+        assert_ne!(delays, pattern(&mut emulated(8)).0);
+
+        assert!(delays.iter().all(|delay| *delay >= Duration::from_millis(30)));
+        assert!(delays.iter().any(|delay| *delay >= Duration::from_millis(230)), "no frame was stalled");
+        assert!(losses.iter().any(|lost| *lost) && !losses.iter().all(|lost| *lost));
+    }
+}
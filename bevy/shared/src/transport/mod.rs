@@ -0,0 +1,411 @@
+use std::fmt::Display;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::mpsc::{channel as mpsc_channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use log::debug;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::codec::Codec;
+use crate::frame::{self, Frame, FrameError, FrameReader, FrameWriter, MessageKind, Stats, HEADER_SIZE};
+
+mod channel;
+mod emulated;
+mod quic;
+mod shm;
+mod tls;
+mod udp;
+
+pub use channel::channel;
+pub use emulated::{emulate, NetworkEmulation};
+pub use quic::{CERTIFICATE_FILE, SERVER_NAME};
+pub use shm::default_path as default_shm_path;
+pub use tls::TlsConfig;
+pub use udp::DATAGRAM_PAYLOAD;
+
+use quic::QuicLink;
+use shm::ShmLink;
+use udp::{UdpLink, UdpListener};
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum TransportKind {
+    #[default]
+    Tcp,
+    /// Reliable, ordered messages for control traffic plus unreliable snapshots of the transforms.
+    Udp,
+    /// TLS over TCP. The certificate is the one to trust, in PEM or DER, and the server name is
+    /// the one it is issued for, [`SERVER_NAME`] by default. Connecting without a certificate
+    /// fails unless `insecure` allows any server.
+    Tls {
+        #[serde(default)]
+        certificate: Option<String>,
+        #[serde(default)]
+        server_name: Option<String>,
+        #[serde(default)]
+        insecure: bool,
+    },
+    /// Separate streams for uploads, state and control messages over TLS. Connecting without a
+    /// certificate fails unless `insecure` allows any server.
+    Quic {
+        #[serde(default)]
+        certificate: Option<String>,
+        #[serde(default)]
+        insecure: bool,
+    },
+    /// Ring buffers in a memory-mapped file, for a server on the same host. Defaults to the file in
+    /// the temporary directory named after the port of the address.
+    Shm {
+        #[serde(default)]
+        path: Option<String>,
+    },
+}
+
+impl Display for TransportKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransportKind::Tcp => f.write_str("tcp"),
+            TransportKind::Udp => f.write_str("udp"),
+            TransportKind::Tls { .. } => f.write_str("tls"),
+            TransportKind::Quic { .. } => f.write_str("quic"),
+            TransportKind::Shm { .. } => f.write_str("shm"),
+        }
+    }
+}
+
+/// Totals of everything that went through a transport, headers included.
+#[derive(Clone, Debug, Default)]
+pub struct Counters {
+    pub sent_frames: u64,
+    pub sent_bytes: u64,
+    pub send_time: Duration,
+    pub received_frames: u64,
+    pub received_bytes: u64,
+    pub receive_time: Duration,
+}
+
+/// Link between the plugin and the physics server. Frames arrive complete and in the order they
+/// were sent, whatever carries them.
+pub trait Transport: Send {
+    fn name(&self) -> &'static str;
+
+    /// Append a CRC32 of the payload to the frames sent from now on.
+    fn set_checksum(&mut self, checksum: bool);
+
+    fn send_frame(&mut self, kind: MessageKind, payload: &[u8], raw_length: usize) -> Result<(), FrameError>;
+
+    fn recv_frame(&mut self) -> Result<Frame, FrameError>;
+
+    /// Whether transforms can leave as unreliable snapshots next to the frames.
+    fn supports_snapshots(&self) -> bool {
+        false
+    }
+
+    fn send_snapshot(&mut self, _chunks: &[Vec<u8>]) -> io::Result<()> {
+        Err(ErrorKind::Unsupported.into())
+    }
+
+    /// Returns the chunks of the newest snapshot that arrived, waiting up to `wait` for the rest.
+    fn recv_snapshot(&mut self, _wait: Duration) -> io::Result<Vec<Vec<u8>>> {
+        Err(ErrorKind::Unsupported.into())
+    }
+
+    fn counters(&self) -> &Counters;
+
+    /// Waits for the peer to receive everything sent so far and closes the link.
+    fn close(&mut self) -> io::Result<()>;
+}
+
+impl dyn Transport + '_ {
+    pub fn send<T: Serialize>(&mut self, kind: MessageKind, codec: &mut dyn Codec, message: &T) -> Result<Stats, FrameError> {
+        let (payload, mut stats) = frame::encode(codec, message)?;
+
+        let instant = Instant::now();
+        self.send_frame(kind, &payload, stats.raw as usize)?;
+        stats.transfer = frame::micros(instant.elapsed());
+
+        Ok(stats)
+    }
+
+    pub fn recv<T: DeserializeOwned>(&mut self, kind: MessageKind, codec: &mut dyn Codec) -> Result<(T, Stats), FrameError> {
+        let instant = Instant::now();
+        let frame = self.recv_frame()?;
+        let transfer = frame::micros(instant.elapsed());
+
+        let (message, mut stats) = frame::decode(codec, &frame, kind)?;
+        stats.transfer = transfer;
+
+        Ok((message, stats))
+    }
+}
+
+/// Reliable, ordered byte stream that frames are written to and read from.
+trait Stream: Read + Write + Send {
+    fn close(&mut self) -> io::Result<()>;
+
+    fn supports_snapshots(&self) -> bool {
+        false
+    }
+
+    fn send_snapshot(&mut self, _chunks: &[Vec<u8>]) -> io::Result<()> {
+        Err(ErrorKind::Unsupported.into())
+    }
+
+    fn recv_snapshot(&mut self, _wait: Duration) -> io::Result<Vec<Vec<u8>>> {
+        Err(ErrorKind::Unsupported.into())
+    }
+}
+
+impl Stream for TcpStream {
+    fn close(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+}
+
+struct Framed<S> {
+    name: &'static str,
+    stream: S,
+    writer: FrameWriter,
+    reader: FrameReader,
+    counters: Counters,
+}
+
+impl<S: Stream + 'static> Framed<S> {
+    fn boxed(name: &'static str, stream: S) -> Box<dyn Transport> {
+        Box::new(Framed { name, stream, writer: FrameWriter::new(false), reader: FrameReader::new(), counters: Counters::default() })
+    }
+}
+
+impl<S: Stream> Transport for Framed<S> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn set_checksum(&mut self, checksum: bool) {
+        self.writer.set_checksum(checksum);
+    }
+
+    fn send_frame(&mut self, kind: MessageKind, payload: &[u8], raw_length: usize) -> Result<(), FrameError> {
+        let instant = Instant::now();
+        self.writer.write_frame(&mut self.stream, kind, payload, raw_length)?;
+
+        self.counters.sent_frames += 1;
+        self.counters.sent_bytes += (HEADER_SIZE + payload.len()) as u64;
+        self.counters.send_time += instant.elapsed();
+
+        Ok(())
+    }
+
+    fn recv_frame(&mut self) -> Result<Frame, FrameError> {
+        let instant = Instant::now();
+        let frame = self.reader.read_frame(&mut self.stream)?;
+
+        self.counters.received_frames += 1;
+        self.counters.received_bytes += (HEADER_SIZE + frame.payload.len()) as u64;
+        self.counters.receive_time += instant.elapsed();
+
+        Ok(frame)
+    }
+
+    fn supports_snapshots(&self) -> bool {
+        self.stream.supports_snapshots()
+    }
+
+    fn send_snapshot(&mut self, chunks: &[Vec<u8>]) -> io::Result<()> {
+        let instant = Instant::now();
+        self.stream.send_snapshot(chunks)?;
+
+        self.counters.sent_bytes += chunks.iter().map(|chunk| chunk.len() as u64).sum::<u64>();
+        self.counters.send_time += instant.elapsed();
+
+        Ok(())
+    }
+
+    fn recv_snapshot(&mut self, wait: Duration) -> io::Result<Vec<Vec<u8>>> {
+        let instant = Instant::now();
+        let chunks = self.stream.recv_snapshot(wait)?;
+
+        self.counters.received_bytes += chunks.iter().map(|chunk| chunk.len() as u64).sum::<u64>();
+        self.counters.receive_time += instant.elapsed();
+
+        Ok(chunks)
+    }
+
+    fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.stream.close()
+    }
+}
+
+/// Frames over a TCP stream connected or accepted elsewhere.
+pub fn tcp(stream: TcpStream) -> io::Result<Box<dyn Transport>> {
+    // Frames are written with a single call, waiting for more data only delays them.
+    stream.set_nodelay(true)?;
+
+    Ok(Framed::boxed("tcp", stream))
+}
+
+pub fn connect(kind: &TransportKind, address: &str) -> io::Result<Box<dyn Transport>> {
+    match kind {
+        TransportKind::Tcp => tcp(TcpStream::connect(address)?),
+        TransportKind::Udp => UdpLink::connect(address).map(|link| Framed::boxed("udp", link)),
+        TransportKind::Tls { certificate, server_name, insecure } => {
+            tls::connect(address, certificate.as_deref(), server_name.as_deref(), *insecure).map(|stream| Framed::boxed("tls", stream))
+        }
+        TransportKind::Quic { certificate, insecure } => {
+            QuicLink::connect(address, certificate.as_deref(), *insecure).map(|link| Framed::boxed("quic", link))
+        }
+        TransportKind::Shm { path } => {
+            let path = match path {
+                Some(path) => path.into(),
+                None => shm::default_path(address.to_socket_addrs()?.next().ok_or(ErrorKind::InvalidInput)?.port()),
+            };
+            ShmLink::connect(&path).map(|link| Framed::boxed("shm", link))
+        }
+    }
+}
+
+/// Accepts transports of every kind it listens on, in the order they connect.
+pub struct Listener {
+    tx: Sender<io::Result<Box<dyn Transport>>>,
+    transports: Receiver<io::Result<Box<dyn Transport>>>,
+    /// Shared memory files, removed with the listener since the threads serving them never end.
+    shm_files: Mutex<Vec<PathBuf>>,
+}
+
+impl Listener {
+    pub fn new() -> Self {
+        let (tx, transports) = mpsc_channel();
+
+        Listener { tx, transports, shm_files: Mutex::new(Vec::new()) }
+    }
+
+    pub fn tcp(&self, address: &str) -> io::Result<()> {
+        self.accept_tcp(TcpListener::bind(address)?);
+
+        Ok(())
+    }
+
+    fn accept_tcp(&self, listener: TcpListener) {
+        let tx = self.tx.clone();
+
+        std::thread::spawn(move || loop {
+            let transport = listener.accept().and_then(|(stream, peer)| {
+                debug!("accepted tcp link from {peer}");
+                tcp(stream)
+            });
+
+            if tx.send(transport).is_err() {
+                break;
+            }
+        });
+    }
+
+    pub fn udp(&self, address: &str) -> io::Result<()> {
+        let mut listener = UdpListener::bind(address)?;
+        let tx = self.tx.clone();
+
+        std::thread::spawn(move || loop {
+            if tx.send(listener.accept().map(|link| Framed::boxed("udp", link))).is_err() {
+                break;
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Accepts TCP connections that start with a TLS handshake presenting the configured certificate.
+    pub fn tls(&self, address: &str, config: &TlsConfig) -> io::Result<()> {
+        let acceptor = tls::TlsAcceptor::new(config)?;
+        let listener = TcpListener::bind(address)?;
+        let tx = self.tx.clone();
+
+        std::thread::spawn(move || loop {
+            let transport = listener.accept().and_then(|(stream, peer)| {
+                debug!("accepted tls link from {peer}");
+                stream.set_nodelay(true)?;
+                acceptor.accept(stream).map(|stream| Framed::boxed("tls", stream))
+            });
+
+            if tx.send(transport).is_err() {
+                break;
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Listens for QUIC connections with the configured certificate, or without one, with a
+    /// freshly generated self-signed certificate written to [`CERTIFICATE_FILE`] for the clients
+    /// that verify it.
+    pub fn quic(&self, address: &str, tls: Option<&TlsConfig>) -> io::Result<()> {
+        let listener = quic::QuicListener::bind(address, tls)?;
+        let tx = self.tx.clone();
+
+        std::thread::spawn(move || {
+            while let Some(connecting) = listener.accept() {
+                let tx = tx.clone();
+                listener.handshake(connecting, move |link| {
+                    let _ = tx.send(link.map(|link| Framed::boxed("quic", link)));
+                });
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Serves clients on the same host through the file at the path, one session at a time. Fails
+    /// if the file already exists.
+    pub fn shm(&self, path: &Path) -> io::Result<()> {
+        let mut listener = shm::ShmListener::bind(path)?;
+        let tx = self.tx.clone();
+        self.shm_files.lock().unwrap().push(path.to_path_buf());
+
+        std::thread::spawn(move || loop {
+            let accepted = listener.accept(|link| {
+                let _ = tx.send(link.map(|link| Framed::boxed("shm", link)));
+            });
+
+            if let Err(e) = accepted {
+                let _ = tx.send(Err(e));
+                break;
+            }
+        });
+
+        Ok(())
+    }
+
+    pub fn accept(&self) -> io::Result<Box<dyn Transport>> {
+        self.transports.recv().unwrap()
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        for path in self.shm_files.get_mut().unwrap().iter() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Default for Listener {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(crate) fn invalid<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, e)
+}
+
+pub(crate) fn unspecified(peer: SocketAddr) -> SocketAddr {
+    match peer {
+        SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+        SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::ToSocketAddrs;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use log::debug;
+use quinn::{ClientConfig, Connecting, Connection, Endpoint, ReadExactError, RecvStream, SendStream, ServerConfig, TransportConfig};
+use tokio::runtime::Runtime;
+
+use super::{invalid, tls, unspecified, Stream, TlsConfig};
+use crate::frame::{Header, MessageKind, HEADER_SIZE};
+
+/// Name clients ask for unless told otherwise, and the self-signed certificate is issued for.
+pub const SERVER_NAME: &str = "bevy-edge";
+/// Where the server stores its certificate in DER, for clients to pin it.
+pub const CERTIFICATE_FILE: &str = "physics-server.der";
+
+/// Streams a side opens, so that a large upload does not hold back the state or the control
+/// messages behind it.
+enum Lane {
+    Control,
+    Bulk,
+    State,
+}
+
+impl Lane {
+    fn of(kind: MessageKind) -> Self {
+        match kind {
+            MessageKind::Request => Lane::Bulk,
+            MessageKind::Response => Lane::State,
+            _ => Lane::Control,
+        }
+    }
+}
+
+type Frames = mpsc::Receiver<io::Result<(u32, Vec<u8>)>>;
+
+fn runtime() -> io::Result<Arc<Runtime>> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .map(Arc::new)
+}
+
+/// Reads whole frames from a stream of the peer and hands them over with their sequence number.
+async fn read_lane(mut stream: RecvStream, frames: mpsc::Sender<io::Result<(u32, Vec<u8>)>>) {
+    loop {
+        let mut header = [0u8; HEADER_SIZE];
+        match stream.read_exact(&mut header).await {
+            Ok(()) => {}
+            Err(ReadExactError::FinishedEarly) => break,
+            Err(ReadExactError::ReadError(e)) => {
+                let _ = frames.send(Err(e.into()));
+                break;
+            }
+        }
+
+        let parsed = match Header::from_bytes(header) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                let _ = frames.send(Err(io::Error::new(ErrorKind::InvalidData, e.to_string())));
+                break;
+            }
+        };
+
+        let mut frame = vec![0u8; HEADER_SIZE + parsed.length as usize];
+        frame[..HEADER_SIZE].copy_from_slice(&header);
+
+        if let Err(e) = stream.read_exact(&mut frame[HEADER_SIZE..]).await {
+            let _ = frames.send(Err(io::Error::new(ErrorKind::UnexpectedEof, e)));
+            break;
+        }
+
+        if frames.send(Ok((parsed.sequence, frame))).is_err() {
+            break;
+        }
+    }
+}
+
+/// QUIC connection carrying frames. Every frame goes to the stream of its [`Lane`], the reader
+/// puts them back in order using their sequence numbers. The server accepts the client moving to
+/// another address, so a handover between networks keeps the connection alive.
+pub struct QuicLink {
+    runtime: Arc<Runtime>,
+    endpoint: Endpoint,
+    connection: Connection,
+    lanes: [Option<SendStream>; 3],
+    frames: Frames,
+    early: HashMap<u32, Vec<u8>>,
+    expected: u32,
+    received: VecDeque<u8>,
+}
+
+impl QuicLink {
+    pub fn connect(address: &str, certificate: Option<&str>, insecure: bool) -> io::Result<Self> {
+        let peer = address.to_socket_addrs()?.next().ok_or(ErrorKind::InvalidInput)?;
+        let runtime = runtime()?;
+
+        let crypto = tls::client_config(certificate, insecure)?;
+
+        let mut transport = TransportConfig::default();
+        transport.keep_alive_interval(Some(Duration::from_secs(5)));
+
+        let mut config = ClientConfig::new(Arc::new(crypto));
+        config.transport_config(Arc::new(transport));
+
+        let endpoint = {
+            let _guard = runtime.enter();
+            let mut endpoint = Endpoint::client(unspecified(peer))?;
+            endpoint.set_default_client_config(config);
+            endpoint
+        };
+
+        let connection = runtime.block_on(async {
+            endpoint.connect(peer, SERVER_NAME).map_err(invalid)?.await.map_err(io::Error::from)
+        })?;
+
+        Ok(QuicLink::new(runtime, endpoint, connection))
+    }
+
+    fn new(runtime: Arc<Runtime>, endpoint: Endpoint, connection: Connection) -> Self {
+        let (tx, frames) = mpsc::channel();
+
+        let incoming = connection.clone();
+        runtime.spawn(async move {
+            loop {
+                match incoming.accept_uni().await {
+                    Ok(stream) => {
+                        tokio::spawn(read_lane(stream, tx.clone()));
+                    }
+                    Err(e) => {
+                        let _ = tx.send(Err(e.into()));
+                        break;
+                    }
+                }
+            }
+        });
+
+        QuicLink {
+            runtime,
+            endpoint,
+            connection,
+            lanes: [None, None, None],
+            frames,
+            early: HashMap::new(),
+            expected: 0,
+            received: VecDeque::new(),
+        }
+    }
+}
+
+impl Stream for QuicLink {
+    /// Finishes the streams, waiting for the peer to acknowledge them, and closes the connection.
+    fn close(&mut self) -> io::Result<()> {
+        let lanes = &mut self.lanes;
+        let connection = &self.connection;
+        let endpoint = &self.endpoint;
+
+        self.runtime.block_on(async {
+            for stream in lanes.iter_mut().flatten() {
+                stream.finish().await?;
+            }
+
+            connection.close(0u8.into(), b"done");
+            endpoint.wait_idle().await;
+
+            Ok(())
+        })
+    }
+}
+
+impl Read for QuicLink {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.received.is_empty() {
+            if let Some(frame) = self.early.remove(&self.expected) {
+                self.received.extend(frame);
+                self.expected = self.expected.wrapping_add(1);
+                break;
+            }
+
+            let (sequence, frame) = self.frames.recv().map_err(|_| io::Error::from(ErrorKind::ConnectionAborted))??;
+            self.early.insert(sequence, frame);
+        }
+
+        let len = buf.len().min(self.received.len());
+        for (dest, byte) in buf.iter_mut().zip(self.received.drain(..len)) {
+            *dest = byte;
+        }
+
+        Ok(len)
+    }
+}
+
+impl Write for QuicLink {
+    /// Expects a whole frame, the header decides which stream carries it.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() < HEADER_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "quic link only carries whole frames"));
+        }
+
+        let header = Header::from_bytes(buf[..HEADER_SIZE].try_into().unwrap())
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        let lane = &mut self.lanes[Lane::of(header.kind) as usize];
+        let connection = &self.connection;
+
+        self.runtime.block_on(async {
+            if lane.is_none() {
+                *lane = Some(connection.open_uni().await?);
+            }
+
+            lane.as_mut().unwrap().write_all(buf).await?;
+
+            Ok::<_, io::Error>(())
+        })?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) struct QuicListener {
+    runtime: Arc<Runtime>,
+    endpoint: Endpoint,
+}
+
+impl QuicListener {
+    /// Presents the configured certificate, or a freshly generated self-signed one that is
+    /// written to [`CERTIFICATE_FILE`] for the clients to pin.
+    pub fn bind(address: &str, tls: Option<&TlsConfig>) -> io::Result<Self> {
+        let address = address.to_socket_addrs()?.next().ok_or(ErrorKind::InvalidInput)?;
+        let runtime = runtime()?;
+
+        let (certificates, key) = match tls {
+            Some(tls) => tls.load()?,
+            None => {
+                let certificate = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()]).map_err(invalid)?;
+                let der = certificate.serialize_der().map_err(invalid)?;
+                std::fs::write(CERTIFICATE_FILE, &der)?;
+                debug!("wrote the certificate of the server to {CERTIFICATE_FILE}");
+
+                (vec![rustls::Certificate(der)], rustls::PrivateKey(certificate.serialize_private_key_der()))
+            }
+        };
+
+        let mut config = ServerConfig::with_single_cert(certificates, key).map_err(invalid)?;
+        // Mobile clients switch between Wi-Fi and cellular, keep their connection when they do.
+        config.migration(true);
+
+        let endpoint = {
+            let _guard = runtime.enter();
+            Endpoint::server(config, address)?
+        };
+
+        Ok(QuicListener { runtime, endpoint })
+    }
+
+    /// Returns `None` once the endpoint is closed.
+    pub fn accept(&self) -> Option<Connecting> {
+        self.runtime.block_on(self.endpoint.accept())
+    }
+
+    /// Completes the handshake on the runtime, so that a slow client does not hold up the ones
+    /// behind it, and hands the link over through `on_link`.
+    pub fn handshake<F: FnOnce(io::Result<QuicLink>) + Send + 'static>(&self, connecting: Connecting, on_link: F) {
+        let runtime = self.runtime.clone();
+        let endpoint = self.endpoint.clone();
+
+        self.runtime.spawn(async move {
+            let link = connecting.await
+                .map(|connection| {
+                    debug!("accepted quic link from {}", connection.remote_address());
+                    QuicLink::new(runtime, endpoint, connection)
+                })
+                .map_err(io::Error::from);
+
+            on_link(link);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::codec::NoneCodec;
+    use crate::transport::{Framed, Transport};
+
+    /// Writes a self-signed certificate for [`SERVER_NAME`] and its key in PEM to the temporary
+    /// directory.
+    fn self_signed(name: &str) -> (TlsConfig, PathBuf) {
+        let certificate = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()]).unwrap();
+        let dir = std::env::temp_dir();
+        let path = |kind: &str| dir.join(format!("bevy-edge-{}-{name}.{kind}.pem", std::process::id()));
+
+        std::fs::write(path("certificate"), certificate.serialize_pem().unwrap()).unwrap();
+        std::fs::write(path("key"), certificate.serialize_private_key_pem()).unwrap();
+
+        let config = TlsConfig {
+            certificate: path("certificate").to_string_lossy().into_owned(),
+            key: path("key").to_string_lossy().into_owned(),
+        };
+
+        (config, path("certificate"))
+    }
+
+    #[test]
+    fn frames_cross_with_the_pinned_certificate() {
+        let (config, certificate) = self_signed("pinned");
+        let listener = QuicListener::bind("127.0.0.1:0", Some(&config)).unwrap();
+        let address = listener.endpoint.local_addr().unwrap().to_string();
+
+        let server = std::thread::spawn(move || {
+            let (tx, rx) = mpsc::channel();
+            listener.handshake(listener.accept().unwrap(), move |link| tx.send(link).unwrap());
+            let mut transport = Framed::boxed("quic", rx.recv().unwrap().unwrap());
+
+            let (request, _) = transport.recv::<String>(MessageKind::Request, &mut NoneCodec).unwrap();
+            transport.send(MessageKind::Response, &mut NoneCodec, &format!("{request} back")).unwrap();
+            transport.close().unwrap();
+        });
+
+        let link = QuicLink::connect(&address, Some(certificate.to_str().unwrap()), false).unwrap();
+        let mut transport: Box<dyn Transport> = Framed::boxed("quic", link);
+
+        transport.send(MessageKind::Request, &mut NoneCodec, &"hello".to_string()).unwrap();
+        let (response, _) = transport.recv::<String>(MessageKind::Response, &mut NoneCodec).unwrap();
+        assert_eq!(response, "hello back");
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn other_certificates_are_refused() {
+        let (config, _) = self_signed("presented");
+        let (_, other) = self_signed("other");
+        let listener = QuicListener::bind("127.0.0.1:0", Some(&config)).unwrap();
+        let address = listener.endpoint.local_addr().unwrap().to_string();
+
+        assert!(QuicLink::connect(&address, Some(other.to_str().unwrap()), false).is_err());
+    }
+
+    #[test]
+    fn no_certificate_needs_insecure() {
+        let (config, _) = self_signed("insecure");
+        let listener = QuicListener::bind("127.0.0.1:0", Some(&config)).unwrap();
+        let address = listener.endpoint.local_addr().unwrap().to_string();
+
+        let e = QuicLink::connect(&address, None, false).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+        assert!(QuicLink::connect(&address, None, true).is_ok());
+    }
+}
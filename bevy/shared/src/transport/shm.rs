@@ -0,0 +1,372 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use memmap2::MmapMut;
+
+use super::Stream;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"BEVYSHM1");
+const VERSION: u32 = 1;
+/// Bytes each direction can hold before the writer waits for the reader.
+const CAPACITY: u64 = 32 * 1024 * 1024;
+const DATA_OFFSET: usize = 4096;
+const SIZE: u64 = DATA_OFFSET as u64 + 2 * CAPACITY;
+
+/// How often each side bumps its heartbeat, and how long the other side waits for it to move
+/// before it takes the peer for dead.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+const PEER_TIMEOUT: Duration = Duration::from_secs(2);
+
+const WAITING: u32 = 0;
+const CONNECTED: u32 = 1;
+
+// Every counter sits on its own cache line, the two processes write them concurrently.
+const MAGIC_OFFSET: usize = 0;
+const VERSION_OFFSET: usize = 8;
+const CAPACITY_OFFSET: usize = 16;
+const STATE_OFFSET: usize = 64;
+const RING_OFFSETS: [usize; 2] = [128, 384];
+const HEAD: usize = 0;
+const TAIL: usize = 64;
+const CLOSED: usize = 128;
+/// Bumped by the side that writes the ring.
+const HEARTBEAT: usize = 192;
+
+/// File of the server listening on the port, so that servers on the same host do not share one.
+pub fn default_path(port: u16) -> PathBuf {
+    std::env::temp_dir().join(format!("bevy-edge-{port}.shm"))
+}
+
+/// Spins first, since the peer usually answers within microseconds, then yields and finally sleeps.
+struct Backoff(u32);
+
+impl Backoff {
+    fn wait(&mut self) {
+        if self.0 < 64 {
+            std::hint::spin_loop();
+        } else if self.0 < 1024 {
+            std::thread::yield_now();
+        } else {
+            std::thread::sleep(Duration::from_micros(50));
+        }
+
+        self.0 = self.0.saturating_add(1);
+    }
+}
+
+/// Notices the peer stopping, as its heartbeat does not move anymore.
+struct Pulse {
+    beat: u64,
+    changed_at: Instant,
+}
+
+impl Pulse {
+    fn new(beat: u64) -> Self {
+        Pulse { beat, changed_at: Instant::now() }
+    }
+
+    fn alive(&mut self, beat: u64) -> bool {
+        if beat != self.beat {
+            self.beat = beat;
+            self.changed_at = Instant::now();
+        }
+
+        self.changed_at.elapsed() < PEER_TIMEOUT
+    }
+}
+
+/// File mapped into both processes: a small control page followed by one ring per direction.
+struct Region {
+    _mmap: MmapMut,
+    base: *mut u8,
+}
+
+// The region only hands out atomics and the byte ranges the ring protocol grants to each side.
+unsafe impl Send for Region {}
+unsafe impl Sync for Region {}
+
+impl Region {
+    fn map(file: &File) -> io::Result<Self> {
+        // SAFETY: the file is only accessed through the atomics and ring ranges below.
+        let mut mmap = unsafe { MmapMut::map_mut(file)? };
+        let base = mmap.as_mut_ptr();
+
+        Ok(Region { _mmap: mmap, base })
+    }
+
+    fn u32_at(&self, offset: usize) -> &AtomicU32 {
+        // SAFETY: offsets are aligned and lie within the control page.
+        unsafe { &*(self.base.add(offset) as *const AtomicU32) }
+    }
+
+    fn u64_at(&self, offset: usize) -> &AtomicU64 {
+        // SAFETY: offsets are aligned and lie within the control page.
+        unsafe { &*(self.base.add(offset) as *const AtomicU64) }
+    }
+
+    fn magic(&self) -> &AtomicU64 {
+        self.u64_at(MAGIC_OFFSET)
+    }
+
+    fn state(&self) -> &AtomicU32 {
+        self.u32_at(STATE_OFFSET)
+    }
+
+    fn head(&self, ring: usize) -> &AtomicU64 {
+        self.u64_at(RING_OFFSETS[ring] + HEAD)
+    }
+
+    fn tail(&self, ring: usize) -> &AtomicU64 {
+        self.u64_at(RING_OFFSETS[ring] + TAIL)
+    }
+
+    fn closed(&self, ring: usize) -> &AtomicU32 {
+        self.u32_at(RING_OFFSETS[ring] + CLOSED)
+    }
+
+    fn heartbeat(&self, ring: usize) -> &AtomicU64 {
+        self.u64_at(RING_OFFSETS[ring] + HEARTBEAT)
+    }
+
+    fn data(&self, ring: usize) -> *mut u8 {
+        // SAFETY: both rings lie within the mapping, see `create`.
+        unsafe { self.base.add(DATA_OFFSET + ring * CAPACITY as usize) }
+    }
+}
+
+fn create(path: &Path) -> io::Result<Region> {
+    let file = match OpenOptions::new().read(true).write(true).create_new(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            let message = format!("{} already exists, remove it if no other server uses it", path.display());
+            return Err(io::Error::new(ErrorKind::AlreadyExists, message));
+        }
+        Err(e) => return Err(e),
+    };
+    file.set_len(SIZE)?;
+
+    let region = Region::map(&file)?;
+    region.u32_at(VERSION_OFFSET).store(VERSION, Ordering::Relaxed);
+    region.u64_at(CAPACITY_OFFSET).store(CAPACITY, Ordering::Relaxed);
+    region.magic().store(MAGIC, Ordering::Release);
+
+    Ok(region)
+}
+
+/// Checks the header and the size of the file before it is mapped, a file that is not one of
+/// ours or is cut short would be read out of bounds.
+fn open(path: &Path) -> io::Result<File> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+
+    let mut header = [0u8; CAPACITY_OFFSET + 8];
+    file.read_exact(&mut header).map_err(|_| io::Error::new(ErrorKind::InvalidData, "shared memory file is not initialized"))?;
+
+    let magic = u64::from_le_bytes(header[MAGIC_OFFSET..MAGIC_OFFSET + 8].try_into().unwrap());
+    let version = u32::from_le_bytes(header[VERSION_OFFSET..VERSION_OFFSET + 4].try_into().unwrap());
+    let capacity = u64::from_le_bytes(header[CAPACITY_OFFSET..CAPACITY_OFFSET + 8].try_into().unwrap());
+
+    if magic != MAGIC {
+        return Err(io::Error::new(ErrorKind::InvalidData, "shared memory file is not initialized"));
+    }
+
+    if version != VERSION || capacity != CAPACITY {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("shared memory file has version {version} and capacity {capacity}, expected {VERSION} and {CAPACITY}")));
+    }
+
+    if file.metadata()?.len() < SIZE {
+        return Err(io::Error::new(ErrorKind::InvalidData, "shared memory file is shorter than its rings"));
+    }
+
+    Ok(file)
+}
+
+/// Byte stream between two processes on the same host through a memory-mapped file, skipping the
+/// network stack entirely. The first ring carries the client's bytes, the second the server's.
+/// Waiting on a peer whose heartbeat stopped fails after [`PEER_TIMEOUT`].
+pub struct ShmLink {
+    region: Arc<Region>,
+    incoming: usize,
+    outgoing: usize,
+    peer: Pulse,
+}
+
+impl ShmLink {
+    pub fn connect(path: &Path) -> io::Result<Self> {
+        let region = Region::map(&open(path)?)?;
+
+        if region.state().compare_exchange(WAITING, CONNECTED, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return Err(io::Error::new(ErrorKind::ConnectionRefused, "shared memory file is in use"));
+        }
+
+        Ok(ShmLink::new(region, 1))
+    }
+
+    /// Beats on the outgoing ring for as long as the link lives.
+    fn new(region: Region, incoming: usize) -> Self {
+        let region = Arc::new(region);
+        let outgoing = 1 - incoming;
+
+        let weak = Arc::downgrade(&region);
+        std::thread::spawn(move || loop {
+            let Some(region) = weak.upgrade() else {
+                break;
+            };
+            region.heartbeat(outgoing).fetch_add(1, Ordering::Release);
+            drop(region);
+
+            std::thread::sleep(HEARTBEAT_INTERVAL);
+        });
+
+        let peer = Pulse::new(region.heartbeat(incoming).load(Ordering::Acquire));
+
+        ShmLink { region, incoming, outgoing, peer }
+    }
+
+    fn check_peer(&mut self) -> io::Result<()> {
+        if self.peer.alive(self.region.heartbeat(self.incoming).load(Ordering::Acquire)) {
+            return Ok(());
+        }
+
+        Err(io::Error::new(ErrorKind::TimedOut, "peer of the shared memory link stopped"))
+    }
+}
+
+impl Stream for ShmLink {
+    /// Marks the outgoing ring as finished, the peer reads the remaining bytes and then sees the end.
+    fn close(&mut self) -> io::Result<()> {
+        self.region.closed(self.outgoing).store(1, Ordering::Release);
+
+        Ok(())
+    }
+}
+
+impl Drop for ShmLink {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+impl Read for ShmLink {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let ring = self.incoming;
+        let tail = self.region.tail(ring).load(Ordering::Relaxed);
+        let mut backoff = Backoff(0);
+
+        let head = loop {
+            let head = self.region.head(ring).load(Ordering::Acquire);
+
+            if head != tail {
+                break head;
+            }
+
+            if self.region.closed(ring).load(Ordering::Acquire) != 0 {
+                // Bytes written right before closing.
+                if self.region.head(ring).load(Ordering::Acquire) == tail {
+                    return Ok(0);
+                }
+
+                continue;
+            }
+
+            backoff.wait();
+            self.check_peer()?;
+        };
+
+        let start = (tail % CAPACITY) as usize;
+        let len = ((head - tail) as usize).min(buf.len()).min(CAPACITY as usize - start);
+
+        // SAFETY: the writer does not touch [tail, head) until the tail moves past it.
+        unsafe { std::ptr::copy_nonoverlapping(self.region.data(ring).add(start), buf.as_mut_ptr(), len) };
+        self.region.tail(ring).store(tail + len as u64, Ordering::Release);
+
+        Ok(len)
+    }
+}
+
+impl Write for ShmLink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let ring = self.outgoing;
+        let head = self.region.head(ring).load(Ordering::Relaxed);
+        let mut backoff = Backoff(0);
+
+        let tail = loop {
+            let tail = self.region.tail(ring).load(Ordering::Acquire);
+
+            if head - tail < CAPACITY {
+                break tail;
+            }
+
+            if self.region.closed(self.incoming).load(Ordering::Acquire) != 0 {
+                return Err(ErrorKind::BrokenPipe.into());
+            }
+
+            backoff.wait();
+            self.check_peer()?;
+        };
+
+        let start = (head % CAPACITY) as usize;
+        let len = ((CAPACITY - (head - tail)) as usize).min(buf.len()).min(CAPACITY as usize - start);
+
+        // SAFETY: the reader does not touch [head, tail + CAPACITY) until the head moves past it.
+        unsafe { std::ptr::copy_nonoverlapping(buf.as_ptr(), self.region.data(ring).add(start), len) };
+        self.region.head(ring).store(head + len as u64, Ordering::Release);
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Serves one session at a time through the file at the path, recreating it for every session.
+/// The file must not exist when the listener binds, and is removed when it is dropped.
+pub(crate) struct ShmListener {
+    path: PathBuf,
+    region: Region,
+}
+
+impl ShmListener {
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        Ok(ShmListener { path: path.to_path_buf(), region: create(path)? })
+    }
+
+    /// Waits for a client and then for the session to end, or for the client to stop, so the file
+    /// can be recreated for the next one. The link is handed over through `on_accept`.
+    pub fn accept<F: FnOnce(io::Result<ShmLink>)>(&mut self, on_accept: F) -> io::Result<()> {
+        while self.region.state().load(Ordering::Acquire) != CONNECTED {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        debug!("accepted shm link on {}", self.path.display());
+
+        on_accept(open(&self.path).and_then(|file| Region::map(&file)).map(|region| ShmLink::new(region, 0)));
+
+        let mut client = Pulse::new(self.region.heartbeat(0).load(Ordering::Acquire));
+        while self.region.closed(0).load(Ordering::Acquire) == 0 || self.region.closed(1).load(Ordering::Acquire) == 0 {
+            if !client.alive(self.region.heartbeat(0).load(Ordering::Acquire)) {
+                warn!("client of {} stopped without closing its link", self.path.display());
+                break;
+            }
+
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // Links of the previous session keep the old file mapped, they never see the new one.
+        std::fs::remove_file(&self.path)?;
+        self.region = create(&self.path)?;
+
+        Ok(())
+    }
+}
+
+impl Drop for ShmListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
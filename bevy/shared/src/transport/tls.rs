@@ -0,0 +1,154 @@
+use std::io::{self, ErrorKind};
+use std::net::{Shutdown, TcpStream};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use log::warn;
+use rustls::{Certificate, ClientConnection, ConnectionCommon, PrivateKey, ServerConnection, ServerName, SideData, StreamOwned};
+use rustls_pemfile::Item;
+use serde::{Deserialize, Serialize};
+
+use super::{invalid, Stream, SERVER_NAME};
+
+/// Certificate chain and private key the server presents, both in PEM.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TlsConfig {
+    pub certificate: String,
+    pub key: String,
+}
+
+impl TlsConfig {
+    pub(crate) fn load(&self) -> io::Result<(Vec<Certificate>, PrivateKey)> {
+        Ok((load_certificates(&self.certificate)?, load_private_key(&self.key)?))
+    }
+}
+
+/// Reads the certificates of a PEM file, or the whole file as a single certificate in DER.
+fn load_certificates(path: &str) -> io::Result<Vec<Certificate>> {
+    let bytes = std::fs::read(path)?;
+    let certificates = rustls_pemfile::certs(&mut bytes.as_slice())?;
+
+    if certificates.is_empty() {
+        return Ok(vec![Certificate(bytes)]);
+    }
+
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(path: &str) -> io::Result<PrivateKey> {
+    let bytes = std::fs::read(path)?;
+    let mut reader = bytes.as_slice();
+
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        if let Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) = item {
+            return Ok(PrivateKey(key));
+        }
+    }
+
+    Err(io::Error::new(ErrorKind::InvalidData, format!("no private key is found in {path}")))
+}
+
+/// Trusts only the given certificate, or any certificate when there is none and `insecure` allows it.
+pub(crate) fn client_config(certificate: Option<&str>, insecure: bool) -> io::Result<rustls::ClientConfig> {
+    let config = match certificate {
+        Some(path) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for certificate in load_certificates(path)? {
+                roots.add(&certificate).map_err(invalid)?;
+            }
+
+            rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots)
+                .with_no_client_auth()
+        }
+        None if !insecure => {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "no certificate is given to verify the physics server, set insecure to accept any"));
+        }
+        None => {
+            warn!("no certificate is given, the identity of the physics server is not verified");
+
+            rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_custom_certificate_verifier(Arc::new(SkipVerification))
+                .with_no_client_auth()
+        }
+    };
+
+    Ok(config)
+}
+
+pub type TlsStream<C> = StreamOwned<C, TcpStream>;
+
+/// Connects and completes the handshake, so that a certificate the client does not trust fails
+/// here rather than on the first frame.
+pub fn connect(address: &str, certificate: Option<&str>, server_name: Option<&str>, insecure: bool) -> io::Result<TlsStream<ClientConnection>> {
+    let name = ServerName::try_from(server_name.unwrap_or(SERVER_NAME)).map_err(invalid)?;
+    let mut connection = ClientConnection::new(Arc::new(client_config(certificate, insecure)?), name).map_err(invalid)?;
+
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+
+    while connection.is_handshaking() {
+        connection.complete_io(&mut stream)?;
+    }
+
+    Ok(StreamOwned::new(connection, stream))
+}
+
+pub(crate) struct TlsAcceptor {
+    config: Arc<rustls::ServerConfig>,
+}
+
+impl TlsAcceptor {
+    pub fn new(config: &TlsConfig) -> io::Result<Self> {
+        let (certificates, key) = config.load()?;
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certificates, key)
+            .map_err(invalid)?;
+
+        Ok(TlsAcceptor { config: Arc::new(config) })
+    }
+
+    /// Does not wait for the handshake, it happens on the first read in the thread of the session
+    /// rather than in the one accepting the clients.
+    pub fn accept(&self, stream: TcpStream) -> io::Result<TlsStream<ServerConnection>> {
+        let connection = ServerConnection::new(self.config.clone()).map_err(invalid)?;
+
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
+
+impl<C, S> Stream for TlsStream<C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>> + Send,
+    S: SideData,
+{
+    /// Tells the peer that nothing is left to read, which it can tell apart from a truncated stream.
+    fn close(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        while self.conn.wants_write() {
+            self.conn.write_tls(&mut self.sock)?;
+        }
+
+        self.sock.shutdown(Shutdown::Write)
+    }
+}
+
+struct SkipVerification;
+
+impl rustls::client::ServerCertVerifier for SkipVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
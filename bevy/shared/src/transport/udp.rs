@@ -0,0 +1,593 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{debug, trace};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use super::{unspecified, Stream};
+
+/// Largest payload carried by a single datagram, small enough to avoid IP fragmentation on
+/// ordinary links.
+pub const DATAGRAM_PAYLOAD: usize = 1200;
+/// Fragments of reliable messages that can be waiting for an acknowledgement at once.
+const WINDOW: usize = 128;
+const RESEND_AFTER: Duration = Duration::from_millis(25);
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+const SOCKET_BUFFER: usize = 4 * 1024 * 1024;
+/// How long a client keeps saying hello to the listener before giving up, and how often.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const HELLO_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a cookie can be presented back.
+const COOKIE_EXPIRY: Duration = Duration::from_secs(5);
+/// Peers that can hold a cookie at once, the hellos of further ones are dropped until some expire.
+const MAX_COOKIES: usize = 4096;
+/// How long an accepted peer has to deliver its first message, and any peer may stay silent after it.
+const FIRST_MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const DATA: u8 = 0;
+const ACK: u8 = 1;
+const SNAPSHOT: u8 = 2;
+/// Sent by a client to the listener, with the cookie it got or with 0, padded so that the cookie
+/// sent back is no larger.
+const HELLO: u8 = 3;
+const COOKIE: u8 = 4;
+/// Tells the client the port of its session socket.
+const WELCOME: u8 = 5;
+
+fn udp_socket(address: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(address), Type::DGRAM, Some(Protocol::UDP))?;
+    // Snapshots are sent in bursts, the default buffers drop a good part of them.
+    socket.set_recv_buffer_size(SOCKET_BUFFER)?;
+    socket.set_send_buffer_size(SOCKET_BUFFER)?;
+    socket.bind(&address.into())?;
+
+    Ok(socket.into())
+}
+
+fn hello(cookie: u64) -> [u8; 9] {
+    let mut datagram = [0u8; 9];
+    datagram[0] = HELLO;
+    datagram[1..].copy_from_slice(&cookie.to_le_bytes());
+
+    datagram
+}
+
+/// Waits for the hellos of new peers. A peer first gets a cookie and its session only once it
+/// presents the cookie back, so that a datagram with a spoofed address cannot start one. Every
+/// session gets its own socket, the welcome tells the peer its port.
+pub struct UdpListener {
+    socket: UdpSocket,
+    cookies: HashMap<SocketAddr, (u64, Instant)>,
+    /// Port of the session socket of every peer whose link is still open.
+    accepted: HashMap<SocketAddr, (u16, Arc<AtomicBool>)>,
+}
+
+impl UdpListener {
+    pub fn bind(address: &str) -> io::Result<Self> {
+        let address = address.to_socket_addrs()?.next().ok_or(ErrorKind::InvalidInput)?;
+
+        Ok(UdpListener { socket: udp_socket(address)?, cookies: HashMap::new(), accepted: HashMap::new() })
+    }
+
+    pub fn accept(&mut self) -> io::Result<UdpLink> {
+        let mut buf = vec![0u8; u16::MAX as usize];
+
+        loop {
+            let (len, peer) = self.socket.recv_from(&mut buf)?;
+
+            let cookie = match &buf[..len] {
+                [HELLO, cookie @ ..] if cookie.len() == 8 => u64::from_le_bytes(cookie.try_into().unwrap()),
+                _ => {
+                    trace!("dropping datagram of {len} bytes from {peer}, it is not a hello");
+                    continue;
+                }
+            };
+
+            self.accepted.retain(|_, (_, closed)| !closed.load(Ordering::Acquire));
+            self.cookies.retain(|_, (_, issued)| issued.elapsed() < COOKIE_EXPIRY);
+
+            // The welcome got lost, the session socket is already waiting for the peer.
+            if let Some((port, _)) = self.accepted.get(&peer) {
+                self.reply(&welcome(*port), peer);
+                continue;
+            }
+
+            match self.cookies.get(&peer) {
+                Some((expected, _)) if *expected == cookie => {}
+                Some((expected, _)) => {
+                    self.reply(&cookie_datagram(*expected), peer);
+                    continue;
+                }
+                None if self.cookies.len() >= MAX_COOKIES => {
+                    trace!("dropping hello of {peer}, too many peers hold a cookie");
+                    continue;
+                }
+                None => {
+                    let cookie = OsRng.next_u64();
+                    self.cookies.insert(peer, (cookie, Instant::now()));
+                    self.reply(&cookie_datagram(cookie), peer);
+                    continue;
+                }
+            }
+
+            let socket = udp_socket(unspecified(peer))?;
+            let port = socket.local_addr()?.port();
+            let closed = Arc::new(AtomicBool::new(false));
+
+            self.cookies.remove(&peer);
+            self.accepted.insert(peer, (port, closed.clone()));
+            self.reply(&welcome(port), peer);
+
+            debug!("accepted udp link from {peer}");
+
+            return Ok(UdpLink::new(socket, peer, true, FIRST_MESSAGE_TIMEOUT, Some(closed)));
+        }
+    }
+
+    /// Peers that cannot be reached are not an error of the listener.
+    fn reply(&self, datagram: &[u8], peer: SocketAddr) {
+        if let Err(e) = self.socket.send_to(datagram, peer) {
+            debug!("failed to answer the hello of {peer}, {e}");
+        }
+    }
+}
+
+fn cookie_datagram(cookie: u64) -> [u8; 9] {
+    let mut datagram = hello(cookie);
+    datagram[0] = COOKIE;
+
+    datagram
+}
+
+fn welcome(port: u16) -> [u8; 3] {
+    let [low, high] = port.to_le_bytes();
+
+    [WELCOME, low, high]
+}
+
+struct Pending {
+    datagram: Vec<u8>,
+    sent_at: Instant,
+}
+
+struct Assembly {
+    parts: Vec<Option<Vec<u8>>>,
+    missing: usize,
+}
+
+impl Assembly {
+    fn new(count: u16) -> Self {
+        Assembly { parts: vec![None; count.max(1) as usize], missing: count.max(1) as usize }
+    }
+
+    fn insert(&mut self, index: u16, payload: &[u8]) {
+        if let Some(part @ None) = self.parts.get_mut(index as usize) {
+            *part = Some(payload.to_vec());
+            self.missing -= 1;
+        }
+    }
+}
+
+/// Whether `a` comes after `b`, tolerating wrapping of the counters.
+fn newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// Datagram link with two channels. Bytes written to it are split into fragments which are resent
+/// until acknowledged and delivered in order, so frames work on top of it as they do on TCP.
+/// Snapshots are sent once, the receiver keeps only the newest one and drops the late ones.
+pub struct UdpLink {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    /// The client knows the port of its session socket from the welcome, and the address the
+    /// socket sends from only once the first datagram of the server arrives.
+    peer_locked: bool,
+    /// When the last datagram of the peer arrived.
+    heard_at: Instant,
+    /// How long the peer may stay silent before the link fails.
+    timeout: Duration,
+    /// Set once the link is gone, so that the listener forgets the peer.
+    closed: Option<Arc<AtomicBool>>,
+    next_message: u32,
+    pending: BTreeMap<(u32, u16), Pending>,
+    expected_message: u32,
+    incoming: HashMap<u32, Assembly>,
+    received: VecDeque<u8>,
+    next_snapshot: u32,
+    snapshot: Option<(u32, Assembly)>,
+    taken_snapshot: Option<u32>,
+    buf: Vec<u8>,
+}
+
+impl UdpLink {
+    /// Says hello to the listener at the address until it welcomes the client.
+    pub fn connect(address: &str) -> io::Result<Self> {
+        let listener = address.to_socket_addrs()?.next().ok_or(ErrorKind::InvalidInput)?;
+        let socket = udp_socket(unspecified(listener))?;
+
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        let mut cookie = 0;
+        let mut buf = vec![0u8; u16::MAX as usize];
+
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(ErrorKind::TimedOut, format!("udp listener at {listener} did not answer")));
+            }
+
+            socket.send_to(&hello(cookie), listener)?;
+            socket.set_read_timeout(Some((deadline - now).min(HELLO_INTERVAL)))?;
+
+            let (len, source) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            };
+
+            if source.port() != listener.port() {
+                continue;
+            }
+
+            match &buf[..len] {
+                [COOKIE, value @ ..] if value.len() == 8 => cookie = u64::from_le_bytes(value.try_into().unwrap()),
+                [WELCOME, low, high] => {
+                    let peer = SocketAddr::new(source.ip(), u16::from_le_bytes([*low, *high]));
+
+                    return Ok(UdpLink::new(socket, peer, false, IDLE_TIMEOUT, None));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn new(socket: UdpSocket, peer: SocketAddr, peer_locked: bool, timeout: Duration, closed: Option<Arc<AtomicBool>>) -> Self {
+        UdpLink {
+            socket,
+            peer,
+            peer_locked,
+            heard_at: Instant::now(),
+            timeout,
+            closed,
+            next_message: 0,
+            pending: BTreeMap::new(),
+            expected_message: 0,
+            incoming: HashMap::new(),
+            received: VecDeque::new(),
+            next_snapshot: 0,
+            snapshot: None,
+            taken_snapshot: None,
+            buf: vec![0u8; u16::MAX as usize],
+        }
+    }
+
+    /// Whether the snapshot is newer than the last one returned.
+    fn unseen(&self, sequence: u32) -> bool {
+        match self.taken_snapshot {
+            Some(taken) => newer(sequence, taken),
+            None => true,
+        }
+    }
+
+    /// Resends the expired fragments and handles at most one datagram, waiting for it up to `timeout`.
+    /// Fails once the peer has been silent for longer than the link allows.
+    fn pump(&mut self, timeout: Duration) -> io::Result<()> {
+        for pending in self.pending.values_mut() {
+            if pending.sent_at.elapsed() >= RESEND_AFTER {
+                trace!("resending {} bytes", pending.datagram.len());
+                self.socket.send_to(&pending.datagram, self.peer)?;
+                pending.sent_at = Instant::now();
+            }
+        }
+
+        self.socket.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+
+        let mut buf = std::mem::take(&mut self.buf);
+        let result = match self.socket.recv_from(&mut buf) {
+            Ok((len, source)) => self.handle(&buf[..len], source),
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => Ok(()),
+            Err(e) => Err(e),
+        };
+        self.buf = buf;
+        result?;
+
+        if self.heard_at.elapsed() >= self.timeout {
+            return Err(io::Error::new(ErrorKind::TimedOut, format!("nothing arrived from {} for {:?}", self.peer, self.timeout)));
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, datagram: &[u8], source: SocketAddr) -> io::Result<()> {
+        if !self.peer_locked && source.port() == self.peer.port() {
+            self.peer = source;
+            self.peer_locked = true;
+        } else if source != self.peer {
+            trace!("dropping datagram from unknown peer {source}");
+            return Ok(());
+        }
+
+        self.heard_at = Instant::now();
+
+        match datagram.first() {
+            Some(&DATA) if datagram.len() >= 9 => {
+                let message = u32::from_le_bytes(datagram[1..5].try_into().unwrap());
+                let index = u16::from_le_bytes(datagram[5..7].try_into().unwrap());
+                let count = u16::from_le_bytes(datagram[7..9].try_into().unwrap());
+
+                let mut ack = [0u8; 7];
+                ack[0] = ACK;
+                ack[1..].copy_from_slice(&datagram[1..7]);
+                self.socket.send_to(&ack, self.peer)?;
+
+                // Already delivered, only the acknowledgement got lost.
+                if newer(self.expected_message, message) {
+                    return Ok(());
+                }
+
+                self.incoming
+                    .entry(message)
+                    .or_insert_with(|| Assembly::new(count))
+                    .insert(index, &datagram[9..]);
+
+                while self.incoming.get(&self.expected_message).is_some_and(|assembly| assembly.missing == 0) {
+                    let assembly = self.incoming.remove(&self.expected_message).unwrap();
+                    self.received.extend(assembly.parts.into_iter().flatten().flatten());
+                    self.expected_message = self.expected_message.wrapping_add(1);
+                    self.timeout = IDLE_TIMEOUT;
+                }
+            }
+            Some(&ACK) if datagram.len() >= 7 => {
+                let message = u32::from_le_bytes(datagram[1..5].try_into().unwrap());
+                let index = u16::from_le_bytes(datagram[5..7].try_into().unwrap());
+
+                self.pending.remove(&(message, index));
+            }
+            Some(&SNAPSHOT) if datagram.len() >= 9 => {
+                let sequence = u32::from_le_bytes(datagram[1..5].try_into().unwrap());
+                let index = u16::from_le_bytes(datagram[5..7].try_into().unwrap());
+                let count = u16::from_le_bytes(datagram[7..9].try_into().unwrap());
+
+                if !self.unseen(sequence) {
+                    trace!("dropping stale snapshot {sequence}");
+                    return Ok(());
+                }
+
+                match &mut self.snapshot {
+                    Some((current, assembly)) if *current == sequence => assembly.insert(index, &datagram[9..]),
+                    Some((current, _)) if newer(*current, sequence) => trace!("dropping stale snapshot {sequence}"),
+                    snapshot => {
+                        let mut assembly = Assembly::new(count);
+                        assembly.insert(index, &datagram[9..]);
+                        *snapshot = Some((sequence, assembly));
+                    }
+                }
+            }
+            _ => trace!("dropping malformed datagram of {} bytes", datagram.len()),
+        }
+
+        Ok(())
+    }
+}
+
+impl Stream for UdpLink {
+    /// Waits until the peer acknowledges everything written, giving up after [`CLOSE_TIMEOUT`].
+    fn close(&mut self) -> io::Result<()> {
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+
+        while !self.pending.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ErrorKind::TimedOut.into());
+            }
+
+            self.pump((deadline - now).min(RESEND_AFTER))?;
+        }
+
+        Ok(())
+    }
+
+    fn supports_snapshots(&self) -> bool {
+        true
+    }
+
+    /// Sends the chunks of a snapshot without waiting for them to arrive. Every chunk has to fit
+    /// into [`DATAGRAM_PAYLOAD`].
+    fn send_snapshot(&mut self, chunks: &[Vec<u8>]) -> io::Result<()> {
+        let sequence = self.next_snapshot;
+        self.next_snapshot = self.next_snapshot.wrapping_add(1);
+
+        let count: u16 = chunks.len().try_into().map_err(|_| io::Error::new(ErrorKind::InvalidInput, "too many snapshot chunks"))?;
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert!(chunk.len() <= DATAGRAM_PAYLOAD, "snapshot chunk of {} bytes does not fit into a datagram", chunk.len());
+
+            let mut datagram = Vec::with_capacity(9 + chunk.len());
+            datagram.push(SNAPSHOT);
+            datagram.extend_from_slice(&sequence.to_le_bytes());
+            datagram.extend_from_slice(&(index as u16).to_le_bytes());
+            datagram.extend_from_slice(&count.to_le_bytes());
+            datagram.extend_from_slice(chunk);
+
+            self.socket.send_to(&datagram, self.peer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the chunks received so far of the newest snapshot that has not been returned yet,
+    /// waiting up to `wait` for its missing chunks. Lost chunks are not recovered, the next
+    /// snapshot supersedes them.
+    fn recv_snapshot(&mut self, wait: Duration) -> io::Result<Vec<Vec<u8>>> {
+        let deadline = Instant::now() + wait;
+
+        loop {
+            let complete = match &self.snapshot {
+                Some((sequence, assembly)) => assembly.missing == 0 && self.unseen(*sequence),
+                None => false,
+            };
+
+            let now = Instant::now();
+            if complete || now >= deadline {
+                break;
+            }
+
+            self.pump(deadline - now)?;
+        }
+
+        match self.snapshot.take() {
+            Some((sequence, assembly)) if self.unseen(sequence) => {
+                if assembly.missing > 0 {
+                    debug!("snapshot {sequence} is missing {} of {} chunks", assembly.missing, assembly.parts.len());
+                }
+
+                self.taken_snapshot = Some(sequence);
+                Ok(assembly.parts.into_iter().flatten().collect())
+            }
+            snapshot => {
+                self.snapshot = snapshot;
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+impl Drop for UdpLink {
+    fn drop(&mut self) {
+        if let Some(closed) = &self.closed {
+            closed.store(true, Ordering::Release);
+        }
+    }
+}
+
+impl Read for UdpLink {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.received.is_empty() {
+            self.pump(RESEND_AFTER)?;
+        }
+
+        let len = buf.len().min(self.received.len());
+        for (dest, byte) in buf.iter_mut().zip(self.received.drain(..len)) {
+            *dest = byte;
+        }
+
+        Ok(len)
+    }
+}
+
+impl Write for UdpLink {
+    /// Sends the whole buffer as one reliable message, blocking only while the window is full.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let message = self.next_message;
+        let count: u16 = buf.chunks(DATAGRAM_PAYLOAD).len().max(1)
+            .try_into()
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "message does not fit into the fragment count"))?;
+
+        for (index, fragment) in buf.chunks(DATAGRAM_PAYLOAD).enumerate() {
+            while self.pending.len() >= WINDOW {
+                self.pump(RESEND_AFTER)?;
+            }
+
+            let index = index as u16;
+            let mut datagram = Vec::with_capacity(9 + fragment.len());
+            datagram.push(DATA);
+            datagram.extend_from_slice(&message.to_le_bytes());
+            datagram.extend_from_slice(&index.to_le_bytes());
+            datagram.extend_from_slice(&count.to_le_bytes());
+            datagram.extend_from_slice(fragment);
+
+            self.socket.send_to(&datagram, self.peer)?;
+            self.pending.insert((message, index), Pending { datagram, sent_at: Instant::now() });
+        }
+
+        self.next_message = self.next_message.wrapping_add(1);
+
+        Ok(buf.len())
+    }
+
+    /// Fragments leave as soon as they are written, unacknowledged ones are resent while reading.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listener() -> (UdpListener, String) {
+        let listener = UdpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.socket.local_addr().unwrap().to_string();
+
+        (listener, address)
+    }
+
+    #[test]
+    fn messages_and_snapshots_cross_loopback() {
+        let (mut listener, address) = listener();
+        let message = (0..5000).map(|i| i as u8).collect::<Vec<u8>>();
+
+        let server = std::thread::spawn(move || {
+            let mut link = listener.accept().unwrap();
+
+            let mut buf = vec![0u8; 5000];
+            link.read_exact(&mut buf).unwrap();
+            link.write_all(&buf).unwrap();
+            link.send_snapshot(&[vec![1, 2, 3], vec![4, 5]]).unwrap();
+            link.close().unwrap();
+        });
+
+        let mut link = UdpLink::connect(&address).unwrap();
+        link.write_all(&message).unwrap();
+
+        let mut echoed = vec![0u8; 5000];
+        link.read_exact(&mut echoed).unwrap();
+        assert_eq!(echoed, message);
+
+        let snapshot = link.recv_snapshot(Duration::from_secs(1)).unwrap();
+        assert_eq!(snapshot, vec![vec![1, 2, 3], vec![4, 5]]);
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn session_starts_only_with_the_cookie() {
+        let (mut listener, address) = listener();
+        listener.socket.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+        socket.send_to(&[DATA, 0, 0, 0, 0, 0, 0, 1, 0], &address).unwrap();
+        socket.send_to(&hello(0), &address).unwrap();
+        socket.send_to(&hello(42), &address).unwrap();
+
+        let e = listener.accept().err().unwrap();
+        assert!(matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut));
+
+        let mut buf = [0u8; 16];
+        let mut cookies = Vec::new();
+        for _ in 0..2 {
+            let (len, _) = socket.recv_from(&mut buf).unwrap();
+            assert_eq!((len, buf[0]), (9, COOKIE));
+            cookies.push(u64::from_le_bytes(buf[1..9].try_into().unwrap()));
+        }
+        assert_eq!(cookies[0], cookies[1]);
+
+        socket.send_to(&hello(cookies[0]), &address).unwrap();
+        listener.accept().unwrap();
+
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        assert_eq!((len, buf[0]), (3, WELCOME));
+    }
+}
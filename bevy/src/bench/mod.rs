@@ -0,0 +1,291 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use bevy_app::{AppExit, Plugin};
+use bevy_ecs::{event::EventReader, schedule::IntoSystemDescriptor, system::{Res, ResMut, Resource}};
+use bevy_time::Time;
+use bevy_window::Windows;
+use serde::Serialize;
+use shared::{codec::{CodecContext, CodecKind}, response::Phases, settings::{PhysicsPlugin, Settings}};
+
+mod output;
+
+use output::{Output, Value};
+pub use output::Format;
+
+const COLUMNS: [&str; 29] = [
+    "timestamp", "frame", "fps", "physics_time", "network_time", "uplink_raw", "uplink_compressed", "downlink_raw",
+    "downlink_compressed", "client_compress_time", "client_decompress_time", "server_compress_time",
+    "server_decompress_time", "codec", "codec_context", "migration_time", "simulated_frame", "network", "uplink_time",
+    "queue_time", "downlink_time", "clock_offset", "insert_time", "broad_phase_time", "narrow_phase_time",
+    "island_construction_time", "solver_time", "ccd_time", "collect_time",
+];
+
+#[derive(Default)]
+pub struct NetworkLog {
+    pub raw: u64,
+    pub compressed: u64,
+}
+
+#[derive(Default)]
+pub struct TimeLog {
+    pub compress: u32,
+    pub decompress: u32,
+}
+
+#[derive(Default, Resource)]
+pub struct PluginLog {
+    pub physics_time: u32,
+    /// Phases of the physics time on the slowest of the servers.
+    pub phases: Phases,
+    pub network_time: u32,
+    pub uplink: NetworkLog,
+    pub downlink: NetworkLog,
+    pub client: TimeLog,
+    pub server: TimeLog,
+    pub codec: Option<(CodecKind, CodecContext)>,
+    /// From asking the server to move the session until the target accepted it, 0 in the other frames.
+    pub migration_time: u32,
+    /// Frame the server simulated up to.
+    pub frame: u64,
+    /// Time the request took to reach the server, from the estimated offset of its clock.
+    pub uplink_time: u32,
+    /// Time the request spent on the server besides stepping the physics.
+    pub queue_time: u32,
+    /// Time the response took to reach the client, from the estimated offset of the server's clock.
+    pub downlink_time: u32,
+    /// Estimated offset of the server's clock from the client's, in microseconds.
+    pub clock_offset: i64,
+}
+
+/// Frames in the first seconds of the run, while the scene is still being set up, are left out of the summary.
+const WARM_UP: f32 = 2.0;
+
+/// What the summary is computed from, one entry per frame after the warm-up.
+#[derive(Default)]
+struct Samples {
+    frame_time: Vec<f32>,
+    physics_time: Vec<f32>,
+    network_time: Vec<f32>,
+    uplink: NetworkLog,
+    downlink: NetworkLog,
+}
+
+#[derive(Resource)]
+struct InternalLog {
+    frame_count: u64,
+    start: std::time::Instant,
+    samples: Samples,
+    output: Output,
+}
+
+#[derive(Serialize)]
+struct Distribution {
+    mean: f32,
+    median: f32,
+    p95: f32,
+    p99: f32,
+}
+
+impl Distribution {
+    fn of(samples: &mut [f32]) -> Self {
+        if samples.is_empty() {
+            return Distribution { mean: 0.0, median: 0.0, p95: 0.0, p99: 0.0 };
+        }
+
+        samples.sort_by(f32::total_cmp);
+        let percentile = |p: f32| samples[((samples.len() as f32 * p).ceil() as usize).clamp(1, samples.len()) - 1];
+
+        Distribution {
+            mean: samples.iter().sum::<f32>() / samples.len() as f32,
+            median: percentile(0.5),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Traffic {
+    raw: u64,
+    compressed: u64,
+    mean_raw: f32,
+    mean_compressed: f32,
+    /// Raw bytes for every compressed one.
+    compression_ratio: f32,
+}
+
+impl Traffic {
+    fn of(log: &NetworkLog, frames: usize) -> Self {
+        Traffic {
+            raw: log.raw,
+            compressed: log.compressed,
+            mean_raw: log.raw as f32 / frames.max(1) as f32,
+            mean_compressed: log.compressed as f32 / frames.max(1) as f32,
+            compression_ratio: if log.compressed == 0 { 0.0 } else { log.raw as f32 / log.compressed as f32 },
+        }
+    }
+}
+
+/// Written at the end of a run, the frame times in milliseconds and the physics and network times in microseconds.
+#[derive(Serialize)]
+struct Summary<'a> {
+    settings: &'a Settings,
+    warm_up: f32,
+    frames: usize,
+    fps: f32,
+    frame_time: Distribution,
+    physics_time: Distribution,
+    network_time: Distribution,
+    uplink: Traffic,
+    downlink: Traffic,
+}
+
+pub struct BenchPlugin {
+    output: Mutex<Option<Output>>,
+}
+
+impl BenchPlugin {
+    /// Creates the file or the file in the directory to write the rows to, and the summary next to
+    /// them, the CSV is printed without a summary if not given.
+    pub fn new(output: Option<&Path>, format: Format, settings: &Settings) -> std::io::Result<Self> {
+        let output = match output {
+            Some(path) => Output::create(path, format, settings, &COLUMNS)?,
+            None => Output::stdout(&COLUMNS),
+        };
+
+        Ok(BenchPlugin { output: Mutex::new(Some(output)) })
+    }
+}
+
+impl Plugin for BenchPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        let output = self.output.lock().unwrap().take().expect("the bench plugin is added once");
+
+        app.insert_resource(InternalLog {
+            frame_count: 0,
+            start: std::time::Instant::now(),
+            samples: Samples::default(),
+            output,
+        })
+        .insert_resource(PluginLog::default());
+
+        app.add_system_set_to_stage(
+            bevy_app::CoreStage::Last,
+            bevy_ecs::schedule::SystemSet::new()
+                .with_system(log)
+                // The last frame is logged before the rows are flushed and summarized.
+                .with_system(close_if_bench_finished.after(log))
+                .with_system(flush_on_exit.after(log)),
+        );
+    }
+}
+
+/// Writes the rows of the last group when the app exits some other way than finishing the bench,
+/// which does not always drop the output.
+fn flush_on_exit(mut exits: EventReader<AppExit>, mut internal_log: ResMut<InternalLog>) {
+    if exits.iter().last().is_some() {
+        if let Err(e) = internal_log.output.flush() {
+            log::error!("Failed to write the last rows of the bench, {e}");
+        }
+    }
+}
+
+fn close_if_bench_finished(time: Res<Time>, mut windows: ResMut<Windows>, settings: Res<Settings>, mut internal_log: ResMut<InternalLog>) {
+    if time.elapsed_seconds_wrapped() > settings.bench_length {
+        windows.iter_mut().for_each(|window| window.close());
+        if let Err(e) = internal_log.output.flush() {
+            log::error!("Failed to write the last rows of the bench, {e}");
+        }
+
+        if let Some(path) = internal_log.output.summary.take() {
+            write_summary(&path, &mut internal_log.samples, &settings);
+        }
+    }
+}
+
+fn write_summary(path: &Path, samples: &mut Samples, settings: &Settings) {
+    let frames = samples.frame_time.len();
+    let duration = samples.frame_time.iter().sum::<f32>() / 1000.0;
+
+    let settings = settings.redacted();
+    let summary = Summary {
+        settings: &settings,
+        warm_up: WARM_UP,
+        frames,
+        fps: if duration == 0.0 { 0.0 } else { frames as f32 / duration },
+        frame_time: Distribution::of(&mut samples.frame_time),
+        physics_time: Distribution::of(&mut samples.physics_time),
+        network_time: Distribution::of(&mut samples.network_time),
+        uplink: Traffic::of(&samples.uplink, frames),
+        downlink: Traffic::of(&samples.downlink, frames),
+    };
+
+    let written = std::fs::File::create(path)
+        .map_err(ron::Error::from)
+        .and_then(|file| ron::ser::to_writer_pretty(file, &summary, ron::ser::PrettyConfig::default()));
+
+    if let Err(e) = written {
+        log::error!("Failed to write the summary of the bench to {}, {e}", path.display());
+    }
+}
+
+fn log(time: Res<Time>, mut internal_log: ResMut<InternalLog>, mut log: ResMut<PluginLog>, settings: Res<Settings>) {
+    let log = std::mem::replace(&mut *log, PluginLog::default());
+
+    let network = match &settings.physics_plugin {
+        PhysicsPlugin::Server { network: Some(network), .. } => network.to_string(),
+        _ => String::new(),
+    };
+
+    let fps = if time.delta_seconds() == 0.0 { 0.0 } else { 1.0 / time.delta_seconds() };
+
+    if time.elapsed_seconds_wrapped() > WARM_UP {
+        let samples = &mut internal_log.samples;
+        samples.frame_time.push(time.delta_seconds() * 1000.0);
+        samples.physics_time.push(log.physics_time as f32);
+        samples.network_time.push(log.network_time as f32);
+        samples.uplink.raw += log.uplink.raw;
+        samples.uplink.compressed += log.uplink.compressed;
+        samples.downlink.raw += log.downlink.raw;
+        samples.downlink.compressed += log.downlink.compressed;
+    }
+
+    let row: [Value; COLUMNS.len()] = [
+        (internal_log.start.elapsed().as_millis() as u64).into(),
+        internal_log.frame_count.into(),
+        fps.into(),
+        log.physics_time.into(),
+        log.network_time.into(),
+        log.uplink.raw.into(),
+        log.uplink.compressed.into(),
+        log.downlink.raw.into(),
+        log.downlink.compressed.into(),
+        log.client.compress.into(),
+        log.client.decompress.into(),
+        log.server.compress.into(),
+        log.server.decompress.into(),
+        log.codec.map(|(codec, _)| codec.to_string()).unwrap_or_default().into(),
+        log.codec.map(|(_, context)| context.to_string()).unwrap_or_default().into(),
+        log.migration_time.into(),
+        log.frame.into(),
+        network.into(),
+        log.uplink_time.into(),
+        log.queue_time.into(),
+        log.downlink_time.into(),
+        log.clock_offset.into(),
+        log.phases.insert.into(),
+        log.phases.broad_phase.into(),
+        log.phases.narrow_phase.into(),
+        log.phases.island_construction.into(),
+        log.phases.solver.into(),
+        log.phases.ccd.into(),
+        log.phases.collect.into(),
+    ];
+
+    if let Err(e) = internal_log.output.write(&row) {
+        log::error!("Failed to write frame {} of the bench, {e}", internal_log.frame_count);
+    }
+
+    internal_log.frame_count += 1;
+}
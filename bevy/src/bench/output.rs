@@ -0,0 +1,255 @@
+//! Where the rows of the bench go. Every row reaches the file before the next frame starts, or with
+//! its row group for the columnar format, so that a run that crashes leaves a file that can be read
+//! up to its last frame.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use shared::settings::Settings;
+
+/// Frames of a row group of the columnar format.
+const ROW_GROUP: usize = 60;
+
+#[derive(Clone, Copy, Default)]
+pub enum Format {
+    #[default]
+    Csv,
+    JsonLines,
+    /// A line of JSON with the metadata, then a line of JSON for every group of rows, holding a list
+    /// of values for every column.
+    Columnar,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "csv" => Some(Format::Csv),
+            "jsonl" => Some(Format::JsonLines),
+            "columnar" => Some(Format::Columnar),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::JsonLines => "jsonl",
+            Format::Columnar => "columnar.jsonl",
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f32),
+    Text(String),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Unsigned(value) => value.fmt(f),
+            Value::Signed(value) => value.fmt(f),
+            Value::Float(value) => value.fmt(f),
+            Value::Text(value) => value.fmt(f),
+        }
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Value::Unsigned(value)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Value::Unsigned(value as u64)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Signed(value)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(value: f32) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+/// Written at the start of every file, to tell the runs apart without their file names.
+#[derive(Serialize)]
+struct Metadata {
+    /// The settings of the run without the token.
+    settings: Settings,
+    /// Profile the client is built with, such as `debug`, `release` or a custom one.
+    profile: &'static str,
+    version: &'static str,
+    host: Host,
+    /// Milliseconds since the UNIX epoch.
+    started_at: u128,
+}
+
+#[derive(Serialize)]
+struct Host {
+    name: Option<String>,
+    os: &'static str,
+    arch: &'static str,
+    cpus: usize,
+}
+
+impl Metadata {
+    fn new(settings: &Settings) -> Metadata {
+        Metadata {
+            settings: settings.redacted(),
+            profile: env!("BUILD_PROFILE"),
+            version: env!("CARGO_PKG_VERSION"),
+            host: Host {
+                name: std::env::var("HOSTNAME")
+                    .ok()
+                    .or_else(|| std::fs::read_to_string("/etc/hostname").ok().map(|name| name.trim().to_string())),
+                os: std::env::consts::OS,
+                arch: std::env::consts::ARCH,
+                cpus: std::thread::available_parallelism().map(|cpus| cpus.get()).unwrap_or(1),
+            },
+            started_at: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis(),
+        }
+    }
+}
+
+pub struct Output {
+    columns: &'static [&'static str],
+    sink: Sink,
+    /// File the summary of the run goes to, none when the rows are printed or once it is written.
+    pub summary: Option<PathBuf>,
+}
+
+enum Sink {
+    /// The CSV without the metadata, as the bench printed it before it could write to files.
+    Stdout,
+    Csv(File),
+    JsonLines(File),
+    Columnar { file: File, group: Vec<Vec<Value>> },
+}
+
+impl Output {
+    pub fn stdout(columns: &'static [&'static str]) -> Self {
+        println!("{}", columns.join(","));
+
+        Output { columns, sink: Sink::Stdout, summary: None }
+    }
+
+    /// Creates the file at the path, or in it named after the settings if it is a directory, and
+    /// writes the metadata to it. The summary goes next to it, with `summary.ron` in place of the
+    /// extension of the format.
+    pub fn create(path: &Path, format: Format, settings: &Settings, columns: &'static [&'static str]) -> io::Result<Self> {
+        let path = if path.is_dir() {
+            path.join(format!("{}.{}", settings, format.extension()))
+        } else {
+            PathBuf::from(path)
+        };
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let stem = match name.strip_suffix(&format!(".{}", format.extension())) {
+            Some(stem) => stem.to_string(),
+            None => path.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+        };
+        let summary = path.with_file_name(format!("{stem}.summary.ron"));
+
+        let mut file = File::create(&path)?;
+        let metadata = serde_json::to_string(&Metadata::new(settings))?;
+
+        let sink = match format {
+            Format::Csv => {
+                writeln!(file, "# {}", metadata)?;
+                writeln!(file, "{}", columns.join(","))?;
+                Sink::Csv(file)
+            }
+            Format::JsonLines => {
+                writeln!(file, "{{\"metadata\":{}}}", metadata)?;
+                Sink::JsonLines(file)
+            }
+            Format::Columnar => {
+                writeln!(file, "{{\"metadata\":{}}}", metadata)?;
+                Sink::Columnar { file, group: columns.iter().map(|_| Vec::with_capacity(ROW_GROUP)).collect() }
+            }
+        };
+
+        Ok(Output { columns, sink, summary: Some(summary) })
+    }
+
+    /// Takes the values in the order of the columns.
+    pub fn write(&mut self, row: &[Value]) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::Stdout => println!("{}", csv(row)),
+            // A single write for every row, so that the file never ends in the middle of one.
+            Sink::Csv(file) => file.write_all(format!("{}\n", csv(row)).as_bytes())?,
+            Sink::JsonLines(file) => {
+                let object: serde_json::Map<_, _> = self.columns
+                    .iter()
+                    .zip(row)
+                    .map(|(column, value)| (column.to_string(), serde_json::to_value(value).unwrap()))
+                    .collect();
+
+                file.write_all(format!("{}\n", serde_json::Value::Object(object)).as_bytes())?;
+            }
+            Sink::Columnar { group, .. } => {
+                for (values, value) in group.iter_mut().zip(row) {
+                    values.push(value.clone());
+                }
+
+                if group[0].len() == ROW_GROUP {
+                    self.flush()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the rows of the columnar format that did not fill a group yet.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Sink::Columnar { file, group } = &mut self.sink {
+            if group[0].is_empty() {
+                return Ok(());
+            }
+
+            let object: serde_json::Map<_, _> = self
+                .columns
+                .iter()
+                .zip(group.iter_mut())
+                .map(|(column, values)| (column.to_string(), serde_json::to_value(std::mem::take(values)).unwrap()))
+                .collect();
+
+            file.write_all(format!("{}\n", serde_json::Value::Object(object)).as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            log::error!("Failed to write the last rows of the bench, {e}");
+        }
+    }
+}
+
+fn csv(row: &[Value]) -> String {
+    row.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(",")
+}
@@ -0,0 +1,166 @@
+//! Runs the scene of the settings with the local physics and against a physics server in the same
+//! process over an in-memory channel, frame by frame, and writes how far the bodies of the two
+//! drift apart as CSV.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use bevy_app::App;
+use bevy_asset::AssetPlugin;
+use bevy_core::CorePlugin;
+use bevy_core_pipeline::CorePipelinePlugin;
+use bevy_ecs::{prelude::Entity, query::With, world::World};
+use bevy_input::InputPlugin;
+use bevy_pbr::PbrPlugin;
+use bevy_render::texture::ImagePlugin;
+use bevy_scene::ScenePlugin;
+use bevy_time::{TimePlugin, TimeUpdateStrategy};
+use bevy_transform::{prelude::Transform, TransformPlugin};
+use bevy_window::WindowPlugin;
+use shared::codec::{CodecContext, CodecKind};
+use shared::settings::{PhysicsPlugin, Settings};
+use shared::transport::{self, Transport, TransportKind};
+
+use crate::bench::PluginLog;
+
+/// Both apps advance by this much every frame, whatever the time it takes them.
+const TIMESTEP: Duration = Duration::from_nanos(16_666_667);
+
+pub fn run(settings: Settings, output: &Path) {
+    let (client, server) = transport::channel();
+    let server = std::thread::spawn(move || {
+        ::physics::serve(server, &::physics::Config::default(), &::physics::Migrations::default())
+    });
+
+    // Taken before the apps exist, so that their first frame takes no time in both.
+    let start = Instant::now();
+
+    let mut local = app(Settings { headless: true, physics_plugin: PhysicsPlugin::Default, ..settings.clone() }, None);
+    let mut remote = app(Settings { headless: true, physics_plugin: remote_plugin(&settings.physics_plugin), ..settings.clone() }, Some(client));
+
+    let frames = (settings.bench_length / TIMESTEP.as_secs_f32()) as u32;
+
+    let mut file = BufWriter::new(File::create(output).unwrap());
+    writeln!(file, "frame,time,max_position_error,mean_position_error,max_rotation_error,missing").unwrap();
+
+    // The local physics writes the bodies back in the frame it steps them, the server's answer is
+    // written back at the start of the next one.
+    advance(&mut remote, start);
+
+    for frame in 0..frames {
+        let now = start + TIMESTEP * frame;
+        advance(&mut local, now);
+        advance(&mut remote, now + TIMESTEP);
+
+        let expected = transforms(&mut local.world);
+        let actual = transforms(&mut remote.world);
+
+        let (mut max, mut sum, mut rotation, mut missing) = (0f32, 0f32, 0f32, 0);
+        for (entity, expected) in &expected {
+            match actual.get(entity) {
+                Some(actual) => {
+                    let error = expected.translation.distance(actual.translation);
+                    max = max.max(error);
+                    sum += error;
+                    // The quaternion and its negation are the same rotation.
+                    rotation = rotation.max((expected.rotation - actual.rotation).length().min((expected.rotation + actual.rotation).length()));
+                }
+                None => missing += 1,
+            }
+        }
+
+        let mean = if expected.len() > missing { sum / (expected.len() - missing) as f32 } else { 0.0 };
+
+        writeln!(file, "{},{},{},{},{},{}", frame, (TIMESTEP * frame).as_secs_f32(), max, mean, rotation, missing).unwrap();
+    }
+
+    file.flush().unwrap();
+
+    // Dropping the app ends its session, which lets the server return.
+    drop(remote);
+    server.join().unwrap();
+}
+
+/// Headless app running the scene of the settings, without a window or the bench, with the
+/// physics over the link if given.
+fn app(settings: Settings, link: Option<Box<dyn Transport>>) -> App {
+    let mut app = App::new();
+
+    app.insert_resource(settings.clone())
+        .insert_resource(PluginLog::default());
+
+    app.add_plugin(CorePlugin::default())
+        .add_plugin(TimePlugin::default())
+        .add_plugin(TransformPlugin::default())
+        .add_plugin(InputPlugin::default())
+        .add_plugin(WindowPlugin::default())
+        .add_plugin(AssetPlugin::default())
+        .add_plugin(ScenePlugin::default());
+
+    crate::add_headless_assets(&mut app);
+
+    app.add_plugin(ImagePlugin::default())
+        .add_plugin(CorePipelinePlugin::default())
+        .add_plugin(PbrPlugin::default());
+
+    crate::add_physics(&mut app, &settings, link);
+
+    app.add_startup_system(crate::shape_collides_with_stack);
+
+    app
+}
+
+/// The plugin of the settings if it offloads the physics, for the server in the process. The
+/// plugin is handed the link to it, the address only names it in the logs.
+fn remote_plugin(plugin: &PhysicsPlugin) -> PhysicsPlugin {
+    let server = String::from("channel");
+
+    match plugin {
+        PhysicsPlugin::Server { codec, codec_context, checksum, generate_scene, .. } => PhysicsPlugin::Server {
+            codec: *codec,
+            codec_context: *codec_context,
+            address: server,
+            transport: TransportKind::Tcp,
+            checksum: *checksum,
+            generate_scene: *generate_scene,
+            token: None,
+            partition: None,
+            migration: None,
+            resume: None,
+            record: None,
+            lockstep: None,
+            network: None,
+        },
+        PhysicsPlugin::Default => PhysicsPlugin::Server {
+            codec: CodecKind::None,
+            codec_context: CodecContext::Reset,
+            address: server,
+            transport: TransportKind::Tcp,
+            checksum: false,
+            generate_scene: false,
+            token: None,
+            partition: None,
+            migration: None,
+            resume: None,
+            record: None,
+            lockstep: None,
+            network: None,
+        },
+    }
+}
+
+fn advance(app: &mut App, now: Instant) {
+    app.insert_resource(TimeUpdateStrategy::ManualInstant(now));
+    app.update();
+}
+
+fn transforms(world: &mut World) -> HashMap<u64, Transform> {
+    world
+        .query_filtered::<(Entity, &Transform), With<crate::Shape>>()
+        .iter(world)
+        .map(|(entity, transform)| (entity.to_bits(), *transform))
+        .collect()
+}
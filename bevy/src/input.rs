@@ -1,10 +1,11 @@
-use std::{net::SocketAddrV4, io::Write};
+use std::{net::{Ipv4Addr, SocketAddrV4}, io::Write};
 
 use bevy_app::{Plugin, CoreStage};
 use bevy_ecs::{system::{Res, Resource}, prelude::EventWriter};
 use bevy_input::{prelude::KeyCode, ButtonState, keyboard::KeyboardInput};
 use bevy_log::prelude::*;
 use crossbeam::channel::{Receiver, bounded, Sender};
+use shared::discovery::{self, Announcement, Service};
 
 #[derive(Resource)]
 struct InputChannel(Receiver<Vec<KeyboardInput>>);
@@ -35,8 +36,25 @@ fn poll_input_channel(rx: Res<InputChannel>, mut keyboard_input: EventWriter<Key
     }
 }
 
+const INPUT_PORT: u16 = 4001;
+
 fn read_remote(tx: Sender<Vec<KeyboardInput>>) {
-    let srv = std::net::TcpListener::bind("0.0.0.0:4001".parse::<SocketAddrV4>().unwrap()).unwrap();
+    let srv = std::net::TcpListener::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, INPUT_PORT)).unwrap();
+
+    // Lets the input apps find this client on the local network.
+    let announced = discovery::respond(Service::Input, || Announcement {
+        service: Service::Input,
+        port: INPUT_PORT,
+        quic_port: None,
+        tls: false,
+        token_required: false,
+        active_sessions: 0,
+        max_sessions: 1,
+    });
+
+    if let Err(e) = announced {
+        error!("could not answer discovery probes, {e:?}");
+    }
 
     while let Err(e) = run_input_server(&tx, &srv) {
         error!(e);
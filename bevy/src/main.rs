@@ -11,7 +11,7 @@ use bevy_input::InputPlugin;
 use bevy_log::LogPlugin;
 use bevy_math::Vec3;
 use bevy_pbr::{AmbientLight, PbrBundle, PbrPlugin, StandardMaterial};
-use bevy_rapier3d::{prelude::{Collider, ColliderMassProperties, RigidBody, Velocity}, rapier::prelude::{Isometry, SharedShape}, render::RapierDebugRenderPlugin};
+use bevy_rapier3d::{prelude::{Collider, ColliderMassProperties, RigidBody, Velocity}, render::RapierDebugRenderPlugin};
 use bevy_render::{
     mesh::MeshPlugin,
     prelude::{Color, Mesh},
@@ -24,9 +24,10 @@ use bevy_time::TimePlugin;
 use bevy_transform::{prelude::Transform, TransformPlugin};
 use bevy_window::WindowPlugin;
 use bevy_winit::WinitPlugin;
-use shared::settings::{PhysicsPlugin, Settings};
+use shared::{scene, settings::{PhysicsPlugin, Settings}, transport::Transport};
 
 mod bench;
+mod determinism;
 mod physics;
 
 #[derive(Component)]
@@ -35,15 +36,24 @@ struct Shape;
 fn main() {
     env_logger::init();
 
-    let settings_path = std::env::args()
-        .collect::<Vec<String>>()
-        .get(1)
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+
+    let settings_path = args
+        .first()
+        .filter(|arg| !arg.starts_with("--"))
         .map(|s| s.to_owned())
         .unwrap_or("Settings.ron".to_string());
 
     let settings: Settings =
         ron::de::from_reader(std::fs::File::open(settings_path).unwrap()).unwrap();
 
+    // Compares the local physics with a server instead of opening the app, see the determinism module.
+    if let Some(position) = args.iter().position(|arg| arg == "--determinism") {
+        let output = args.get(position + 1).expect("--determinism needs the file to write the CSV to");
+        determinism::run(settings, std::path::Path::new(output));
+        return;
+    }
+
     let mut app = App::new();
 
     if let Some(level) = &settings.tracing_level {
@@ -60,6 +70,17 @@ fn main() {
         });
     }
 
+    let output = args.iter().position(|arg| arg == "--output").map(|position| {
+        args.get(position + 1).expect("--output needs the file or directory to write the rows to")
+    });
+    let format = args.iter().position(|arg| arg == "--format").map_or(bench::Format::Csv, |position| {
+        args.get(position + 1).and_then(|format| bench::Format::parse(format)).expect("--format needs one of csv, jsonl or columnar")
+    });
+    let bench = bench::BenchPlugin::new(output.map(std::path::Path::new), format, &settings).unwrap_or_else(|e| {
+        eprintln!("failed to create the output of the bench, {e}");
+        std::process::exit(1);
+    });
+
     app.insert_resource(settings.clone());
 
     // For scripting purposes, we run the event loop even the window get unfocused.
@@ -84,15 +105,10 @@ fn main() {
         .add_plugin(AssetPlugin::default())
         .add_plugin(ScenePlugin::default())
         .add_plugin(WinitPlugin::default())
-        .add_plugin(bench::BenchPlugin::default());
+        .add_plugin(bench);
 
     if settings.headless {
-        app.add_asset::<Shader>()
-            .add_debug_asset::<Shader>()
-            .init_asset_loader::<ShaderLoader>()
-            .init_debug_asset_loader::<ShaderLoader>();
-
-        app.add_plugin(MeshPlugin);
+        add_headless_assets(&mut app);
     } else {
         app.add_plugin(RenderPlugin::default());
     }
@@ -101,6 +117,27 @@ fn main() {
         .add_plugin(CorePipelinePlugin::default())
         .add_plugin(PbrPlugin::default());
 
+    add_physics(&mut app, &settings, None);
+
+    app
+        .add_startup_system(shape_collides_with_stack)
+        .add_system(bevy_window::close_on_esc);
+
+    app.run()
+}
+
+/// Assets the scene needs when nothing is rendered.
+fn add_headless_assets(app: &mut App) {
+    app.add_asset::<Shader>()
+        .add_debug_asset::<Shader>()
+        .init_asset_loader::<ShaderLoader>()
+        .init_debug_asset_loader::<ShaderLoader>();
+
+    app.add_plugin(MeshPlugin);
+}
+
+/// Adds the physics of the settings, over the link if given instead of connecting to the server.
+fn add_physics(app: &mut App, settings: &Settings, link: Option<Box<dyn Transport>>) {
     match &settings.physics_plugin {
         PhysicsPlugin::Default => {
             app.add_plugin(bevy_rapier3d::plugin::RapierPhysicsPlugin::<
@@ -116,15 +153,10 @@ fn main() {
         PhysicsPlugin::Server { address, .. } => {
             app.add_plugin(physics::RapierPhysicsPlugin {
                 address: address.clone(),
+                link: std::sync::Mutex::new(link),
             });
         }
     }
-
-    app
-        .add_startup_system(shape_collides_with_stack)
-        .add_system(bevy_window::close_on_esc);
-
-    app.run()
 }
 
 fn shape_collides_with_stack(
@@ -144,120 +176,64 @@ fn shape_collides_with_stack(
         ..Default::default()
     });
 
-    let length = 15.0;
-    let thickness = 1.0;
-
-    /* Create the ground. */
-    let walls = if let shared::settings::Room::Closed = settings.scene.room {
-        vec![
-            ((length * 2.0, thickness, length * 2.0), (0.0, -thickness, 0.0)),
-            ((length * 2.0, thickness, length * 2.0), (0.0, length * 10.0 - thickness, 0.0)),
-            ((thickness, length * 10.0, length * 2.0), (-length, 0.0, 0.0)),
-            ((thickness, length * 10.0, length * 2.0), (length, 0.0, 0.0)),
-            ((length, length * 10.0, thickness), (0.0, 0.0, length * 2.0)),
-            ((length, length * 10.0, thickness), (0.0, 0.0, -length * 2.0)),
-        ]
-    } else {
-        vec![
-            ((length * 2.0, thickness, length * 2.0), (0.0, -thickness, 0.0)),
-        ]
-    };
-
-    for wall in walls {
-        commands
-            .spawn(Collider::cuboid(wall.0.0, wall.0.1, wall.0.2))
-            .insert(RigidBody::Fixed)
-            .insert(PbrBundle {
-                mesh: meshes.add(bevy_render::prelude::shape::Box::new(wall.0.0 * 2.0, wall.0.1 * 2.0, wall.0.2 * 2.0).into()),
-                material: materials.add(Color::BLACK.into()),
-                transform: Transform::from_xyz(wall.1.0, wall.1.1, wall.1.2),
-                ..Default::default()
-            });
-    }
+    let generate_scene = matches!(settings.physics_plugin, PhysicsPlugin::Server { generate_scene: true, .. });
+    let mut scene_entities = Vec::new();
 
-    let num = 10;
-    let rad = 0.4;
-
-    let (collider, mesh): (Collider, Mesh) = match settings.scene.shape.as_str() {
-        "cuboid" => (Collider::cuboid(rad, rad, rad), bevy_render::prelude::shape::Cube::new(rad * 2.0).into()),
-        "capsule" => (Collider::capsule_y(rad, rad), bevy_render::prelude::shape::Capsule { radius: rad, depth: rad * 2.0, ..Default::default() }.into()),
-        "complex" => {
-            let cuboid_rad = rad / 2.0;
-
-            let shapes = vec![
-                (
-                    Isometry::identity(),
-                    SharedShape::cuboid(cuboid_rad, cuboid_rad, cuboid_rad),
-                ),
-                (
-                    Isometry::translation(cuboid_rad, cuboid_rad, 0.0),
-                    SharedShape::cuboid(cuboid_rad, cuboid_rad, cuboid_rad),
-                ),
-                (
-                    Isometry::translation(-cuboid_rad, cuboid_rad, 0.0),
-                    SharedShape::cuboid(cuboid_rad, cuboid_rad, cuboid_rad),
-                ),
-            ];
-
-            let collider = SharedShape::compound(shapes);
-
-            (collider.into(), bevy_render::prelude::shape::Box::new(rad, rad, rad).into())
-        },
-        _ => (Collider::ball(rad), bevy_render::prelude::shape::Icosphere { radius: rad, ..Default::default() }.into()),
-    };
-
-    let shift = rad * 2.0 + 0.005;
-    let centerx = shift * (num as f32) / 2.0;
-    let centery = shift / 2.0;
-    let centerz = shift * (num as f32) / 2.0;
-
-    let height = settings.scene.num_object / num / num;
-    let density = 0.477;
-
-    for i in 0..num {
-        for j in 0usize..height {
-            for k in 0..num {
-                let x = i as f32 * shift - centerx;
-                let y = j as f32 * shift + centery;
-                let z = k as f32 * shift - centerz;
-
-                let color = Color::rgb(
-                    (i % 3) as f32 * 0.33,
-                    (j % 3) as f32 * 0.33,
-                    (k % 3) as f32 * 0.33,
-                );
-
-                commands
-                    .spawn(RigidBody::Dynamic)
-                    .insert(collider.clone())
-                    .insert(ColliderMassProperties::Density(density))
-                    .insert(bevy_rapier3d::geometry::Restitution { coefficient: settings.scene.restitution, combine_rule: bevy_rapier3d::prelude::CoefficientCombineRule::Max })
-                    .insert(bevy_rapier3d::geometry::Friction { coefficient: 0.0, combine_rule: bevy_rapier3d::prelude::CoefficientCombineRule::Max })
-                    .insert(Shape)
-                    .insert(bevy_rapier3d::dynamics::Ccd { enabled: settings.scene.ccd })
-                    .insert(PbrBundle {
-                        mesh: meshes.add(mesh.clone()),
-                        material: materials.add(color.into()),
-                        transform: Transform::from_xyz(x, y, z),
-                        ..Default::default()
-                    });
-            }
-        }
-    }
+    for body in scene::bodies(&settings.scene) {
+        let mesh: Mesh = match body.shape {
+            scene::Shape::Ball(radius) => bevy_render::prelude::shape::Icosphere { radius, ..Default::default() }.into(),
+            scene::Shape::Capsule { half_height, radius } => bevy_render::prelude::shape::Capsule { radius, depth: half_height * 2.0, ..Default::default() }.into(),
+            scene::Shape::Cuboid(hx, hy, hz) => bevy_render::prelude::shape::Box::new(hx * 2.0, hy * 2.0, hz * 2.0).into(),
+            scene::Shape::Complex(rad) => bevy_render::prelude::shape::Box::new(rad * 2.0, rad * 2.0, rad * 2.0).into(),
+        };
 
-    commands
-        .spawn(RigidBody::Dynamic)
-        .insert(Collider::ball(1.0))
-        .insert(ColliderMassProperties::Density(1.0))
-        .insert(Shape)
-        .insert(Velocity::linear(Vec3::new(0.0, 0.0, -30.0)))
-        .insert(PbrBundle {
-            mesh: meshes.add(bevy_render::prelude::shape::Icosphere { radius: 1.0, ..Default::default() }.into()),
-            material: materials.add(Color::rgb(0.0, 0.0, 0.0).into()),
-            transform: Transform::from_xyz(0.0, 2.0, length - 2.0),
+        let mut entity = commands.spawn(PbrBundle {
+            mesh: meshes.add(mesh),
+            material: materials.add(Color::rgb(body.color.0, body.color.1, body.color.2).into()),
+            transform: Transform::from_xyz(body.translation.0, body.translation.1, body.translation.2),
             ..Default::default()
         });
 
+        if body.dynamic {
+            entity.insert(Shape);
+        }
+
+        scene_entities.push(entity.id());
+
+        // The server already has the bodies of a generated scene, these entities are only rendered.
+        if generate_scene {
+            continue;
+        }
+
+        entity
+            .insert(Collider::from(body.shape.shared_shape()))
+            .insert(if body.dynamic { RigidBody::Dynamic } else { RigidBody::Fixed });
+
+        if let Some(density) = body.density {
+            entity.insert(ColliderMassProperties::Density(density));
+        }
+
+        if let Some(coefficient) = body.restitution {
+            entity.insert(bevy_rapier3d::geometry::Restitution { coefficient, combine_rule: bevy_rapier3d::prelude::CoefficientCombineRule::Max });
+        }
+
+        if let Some(coefficient) = body.friction {
+            entity.insert(bevy_rapier3d::geometry::Friction { coefficient, combine_rule: bevy_rapier3d::prelude::CoefficientCombineRule::Max });
+        }
+
+        if body.ccd {
+            entity.insert(bevy_rapier3d::dynamics::Ccd::enabled());
+        }
+
+        if body.linvel != (0.0, 0.0, 0.0) {
+            entity.insert(Velocity::linear(Vec3::new(body.linvel.0, body.linvel.1, body.linvel.2)));
+        }
+    }
+
+    if generate_scene {
+        commands.insert_resource(physics::SceneEntities(scene_entities));
+    }
+
     commands.insert_resource(AmbientLight {
         color: Color::WHITE,
         brightness: 2.5,
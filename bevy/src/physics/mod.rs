@@ -1,4 +1,4 @@
 mod plugin;
 mod systems;
 
-pub use plugin::RapierPhysicsPlugin;
+pub use plugin::{RapierPhysicsPlugin, SceneEntities};
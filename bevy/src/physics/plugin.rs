@@ -1,4 +1,5 @@
-use std::io::{Write, BufWriter, BufReader, Read};
+use std::path::Path;
+use std::sync::Mutex;
 
 use bevy_log::info_span;
 use bevy_rapier3d::prelude::RapierConfiguration;
@@ -10,58 +11,21 @@ use bevy_ecs::{
 };
 use crossbeam::channel::{Sender, Receiver, bounded};
 
-use shared::deflate::{Compressor, Decompressor, CONFIG};
-use shared::{request::Request, response::{Response, SyncContext}};
+use shared::clock::{self, ClockSync, Exchange, Latency};
+use shared::codec::{Codec, CodecContext, CodecKind, NoneCodec};
+use shared::discovery::{self, Service};
+use shared::frame::{micros, FrameError, MessageKind};
+use shared::handshake::Handshake;
+use shared::migration::{Migration, Moved};
+use shared::partition::Router;
+use shared::recording::Recorder;
+use shared::settings::{PhysicsPlugin, Settings};
+use shared::transport::{Transport, TransportKind};
+use shared::{request::Request, response::{Log, Phases, Response, SyncContext}};
 use crate::bench::{PluginLog, NetworkLog, TimeLog};
 
 use super::systems;
 
-struct LogReader<R> {
-    reader: R,
-    read_bytes: usize,
-}
-
-impl<R> LogReader<R> {
-    fn new(reader: R) -> Self {
-        LogReader { reader, read_bytes: 0 }
-    }
-}
-
-impl<R: Read> Read for LogReader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.reader.read(buf)
-            .map(|read_bytes| {
-                self.read_bytes += read_bytes;
-                read_bytes
-            })
-    }
-}
-
-struct LogWriter<W> {
-    writer: W,
-    written_bytes: usize,
-}
-
-impl<W> LogWriter<W> {
-    fn new(writer: W) -> Self {
-        LogWriter { writer, written_bytes: 0 }
-    }
-}
-
-impl<W: Write> Write for LogWriter<W> {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.writer.write(buf)
-            .map(|written_bytes| {
-                self.written_bytes += written_bytes;
-                written_bytes
-            })
-    }
-
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.writer.flush()
-    }
-}
-
 #[derive(Resource)]
 pub struct RequestSender(pub Sender<Request>);
 #[derive(Resource)]
@@ -73,6 +37,10 @@ pub struct RigidBody(pub Vec<bevy_rapier3d::rapier::dynamics::RigidBody>);
 #[derive(Resource)]
 pub struct Collider(pub Vec<bevy_rapier3d::rapier::prelude::ColliderBuilder>);
 
+/// Entities spawned for a scene generated by the server, indexed by their position in the scene.
+#[derive(Resource)]
+pub struct SceneEntities(pub Vec<bevy_ecs::prelude::Entity>);
+
 #[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
 enum PhysicsStage {
     SyncBackend,
@@ -84,8 +52,17 @@ pub struct LocalContext {
     pub physics_scale: f32,
 }
 
+/// How long to wait for the missing chunks of a snapshot once the rest of the response arrived.
+const SNAPSHOT_WAIT: std::time::Duration = std::time::Duration::from_millis(5);
+
+/// How long to collect the answers of the physics servers when the address is [`discovery::AUTO`].
+const DISCOVERY_WAIT: std::time::Duration = std::time::Duration::from_millis(500);
+
 pub struct RapierPhysicsPlugin {
     pub address: String,
+    /// Link to a server already connected, used instead of connecting to the address for the
+    /// first session.
+    pub link: Mutex<Option<Box<dyn Transport>>>,
 }
 
 impl Plugin for RapierPhysicsPlugin {
@@ -93,58 +70,121 @@ impl Plugin for RapierPhysicsPlugin {
         let (req_tx, req_rx) = bounded(1);
         let (res_tx, res_rx) = bounded(1);
 
-        let settings = app.world.get_resource::<shared::settings::Settings>().unwrap().clone();
+        let settings = app.world.get_resource::<Settings>().unwrap().clone();
         let address = self.address.clone();
+        let mut link = self.link.lock().unwrap().take();
 
         std::thread::spawn(move || {
             log::debug!("Plugin thread is started");
-            let tcp_stream = std::net::TcpStream::connect(address).unwrap();
-            log::debug!("TCP connection is established");
 
-            bincode::serde::encode_into_std_write(&settings, &mut &tcp_stream, CONFIG).unwrap();
+            let (transport, partition, migration, record) = match &settings.physics_plugin {
+                PhysicsPlugin::Server { transport, partition, migration, record, .. } => (transport.clone(), partition.clone(), migration.clone(), record.clone()),
+                PhysicsPlugin::Default => (TransportKind::Tcp, None, None, None),
+            };
+
+            let address = if address == discovery::AUTO {
+                let server = discovery::pick(Service::Physics, DISCOVERY_WAIT).unwrap()
+                    .expect("no physics server answered the discovery probe");
+                server.address(&transport)
+            } else {
+                address
+            };
 
-            let compress = match settings.physics_plugin {
-                shared::settings::PhysicsPlugin::Server { compress, .. } => compress,
-                shared::settings::PhysicsPlugin::Default => None,
+            // Every region of a partitioned world runs on its own server, in the order of the regions.
+            let mut sessions: Vec<Session> = match &partition {
+                Some(partition) => {
+                    assert_eq!(partition.servers.len(), partition.bounds.len(), "partition needs a server for every region after the first one");
+
+                    std::iter::once(address)
+                        .chain(partition.servers.iter().cloned())
+                        .enumerate()
+                        .map(|(index, address)| {
+                            let mut settings = settings.clone();
+                            if let PhysicsPlugin::Server { partition: Some(partition), .. } = &mut settings.physics_plugin {
+                                partition.index = index;
+                            }
+
+                            Session::open(&transport, address, &settings, link.take())
+                        })
+                        .collect()
+                }
+                None => vec![Session::open(&transport, address, &settings, link.take())],
             };
+            let mut router = partition.map(Router::new);
+            let mut frame = 0;
+
+            // The traffic is recorded as the app sees it, before a partition splits it among the servers.
+            let mut recorder = record.map(|path| Recorder::create(Path::new(&path), &settings).unwrap());
 
             res_tx.send((Response::SyncContext(SyncContext::default()), PluginLog::default())).unwrap();
 
-            while let Ok(req) = {
+            'frames: while let Ok(req) = {
                 let _span = info_span!("request_received_over_channel").entered();
                 let req = req_rx.recv();
                 log::debug!("request is received from bevy");
                 req
             }{
+                if let Some(recorder) = &mut recorder {
+                    recorder.request(&req).unwrap();
+                }
+
+                // Answered with nothing, so the frame is not over yet.
+                if let Request::Snapshot = req {
+                    for session in sessions.iter_mut() {
+                        if let Err(e) = session.link.send(MessageKind::Request, session.codec.as_mut(), &req) {
+                            log::error!("Failed to send request, {e}");
+                            break 'frames;
+                        }
+                    }
+
+                    continue;
+                }
+
                 let mut uplink = NetworkLog::default();
                 let mut downlink = NetworkLog::default();
                 let mut comp_time = TimeLog::default();
+                let mut migration_time = 0;
+
+                if let Some(migration) = migration.as_ref().filter(|migration| migration.frame == frame) {
+                    let _span = info_span!("migration").entered();
+                    let instant = std::time::Instant::now();
+
+                    match sessions.as_mut_slice() {
+                        [session] => {
+                            if let Err(e) = session.migrate(&transport, migration, &settings) {
+                                log::error!("Failed to migrate, {e}");
+                                break 'frames;
+                            }
+                        }
+                        _ => log::warn!("sessions of a partitioned world do not migrate"),
+                    }
+
+                    migration_time = micros(instant.elapsed());
+                }
+                frame += 1;
+
+                let requests: Vec<Request> = match (&mut router, req) {
+                    (Some(router), Request::SyncContext(ctx)) => router.split(ctx).into_iter().map(Request::SyncContext).collect(),
+                    (_, req) => vec![req],
+                };
 
                 {
                     let _span = info_span!("request_sent").entered();
 
-                    if let Some(level) = compress {
-                        let mut compressor = BufWriter::new(Compressor::new(&tcp_stream, level));
-                        bincode::serde::encode_into_std_write(req, &mut compressor, CONFIG).unwrap();
-                        compressor.flush().unwrap();
-
-                        let Ok(compressor) = compressor.into_inner() else {
-                            panic!("failed to get into inner of compressor");
+                    // The servers work on their requests while the rest of them are sent.
+                    for (session, req) in sessions.iter_mut().zip(&requests) {
+                        let stats = match session.link.send(MessageKind::Request, session.codec.as_mut(), req) {
+                            Ok(stats) => stats,
+                            Err(e) => {
+                                log::error!("Failed to send request, {e}");
+                                break 'frames;
+                            }
                         };
-
-                        uplink.raw = compressor.total_in();
-                        uplink.compressed = compressor.total_out();
-                        comp_time.compress = compressor.elapsed();
-                    } else {
-                        let mut writer = BufWriter::new(LogWriter::new(&tcp_stream));
-                        bincode::serde::encode_into_std_write(req, &mut writer, CONFIG).unwrap();
-                        writer.flush().unwrap();
-                        uplink.raw = writer.into_inner().map_err(|_| "failed to get inner of buffer writer").unwrap().written_bytes.try_into().unwrap();
+                        session.sent_at = clock::now();
+                        uplink.raw += stats.raw;
+                        uplink.compressed += stats.compressed;
+                        comp_time.compress += stats.elapsed;
                     }
-
-                    // Force flushing the buffer
-                    tcp_stream.set_nodelay(true).unwrap();
-                    tcp_stream.set_nodelay(false).unwrap();
                 }
 
                 log::debug!("request is sent to physics");
@@ -153,38 +193,64 @@ impl Plugin for RapierPhysicsPlugin {
                     let _span = info_span!("response_received").entered();
                     let instant = std::time::Instant::now();
 
-                    let log: shared::response::Log;
-
-                    let ctx = if compress.is_some() {
-                        let mut decompressor = BufReader::new(Decompressor::new(&tcp_stream));
-                        let res = bincode::serde::decode_from_std_read(&mut decompressor, CONFIG).unwrap();
-                        let mut decompressor = decompressor.into_inner();
-                        decompressor.finish().unwrap();
-                        downlink.raw = decompressor.total_out();
-                        downlink.compressed = decompressor.total_in();
-                        comp_time.decompress = decompressor.elapsed();
-                        log = bincode::serde::decode_from_std_read(&mut BufReader::with_capacity(1024, &tcp_stream), CONFIG).unwrap();
-
-                        res
-                    } else {
-                        let mut reader = BufReader::new(LogReader::new(&tcp_stream));
-                        let res = bincode::serde::decode_from_std_read(&mut reader, CONFIG).unwrap();
-                        log = bincode::serde::decode_from_std_read(&mut reader, CONFIG).unwrap();
-                        downlink.raw = reader.into_inner().read_bytes.try_into().unwrap();
-
-                        res
+                    let mut responses = Vec::with_capacity(sessions.len());
+                    let mut physics_time = 0;
+                    let mut phases = Phases::default();
+                    let mut server = TimeLog::default();
+                    let mut latency = Latency::default();
+                    let mut queue_time = 0;
+
+                    for session in &mut sessions {
+                        let (ctx, log, session_latency) = match session.receive(&mut downlink, &mut comp_time) {
+                            Ok(received) => received,
+                            Err(e) => {
+                                log::error!("Failed to receive response, {e}");
+                                break 'frames;
+                            }
+                        };
+
+                        // The slowest of the servers holds the frame back.
+                        if log.physics_time >= physics_time {
+                            phases = log.phases;
+                        }
+                        physics_time = physics_time.max(log.physics_time);
+                        server.compress = server.compress.max(log.compress_time);
+                        server.decompress = server.decompress.max(log.decompress_time);
+                        latency.uplink = latency.uplink.max(session_latency.uplink);
+                        latency.downlink = latency.downlink.max(session_latency.downlink);
+                        queue_time = queue_time.max(session_latency.server.saturating_sub(log.physics_time));
+
+                        responses.push(ctx);
+                    }
+
+                    let ctx = match &mut router {
+                        Some(router) => router.merge(responses),
+                        None => responses.pop().unwrap(),
                     };
 
                     let plugin_log = PluginLog {
-                        physics_time: log.physics_time,
-                        network_time: instant.elapsed().as_micros().try_into().unwrap(),
+                        physics_time,
+                        phases,
+                        network_time: micros(instant.elapsed()),
                         uplink,
                         downlink,
                         client: comp_time,
-                        server: TimeLog { compress: log.compress_time, decompress: log.decompress_time }
+                        server,
+                        codec: Some(sessions[0].negotiated),
+                        migration_time,
+                        frame: ctx.frame,
+                        uplink_time: latency.uplink,
+                        queue_time,
+                        downlink_time: latency.downlink,
+                        clock_offset: sessions[0].clock.offset(),
                     };
 
-                    if let Err(e) = res_tx.send((ctx, plugin_log)) {
+                    let response = Response::SyncContext(ctx);
+                    if let Some(recorder) = &mut recorder {
+                        recorder.response(&response).unwrap();
+                    }
+
+                    if let Err(e) = res_tx.send((response, plugin_log)) {
                         log::debug!("Failed to send response {e:?}");
                         break;
                     }
@@ -192,12 +258,14 @@ impl Plugin for RapierPhysicsPlugin {
             }
             log::debug!("Shuting down the Plugin thread");
 
-            if let Some(level) = compress {
-                let mut compressor = BufWriter::new(Compressor::new(&tcp_stream, level));
-                bincode::serde::encode_into_std_write(Request::Shutdown, &mut compressor, CONFIG).unwrap();
-                compressor.flush().unwrap();
-            } else {
-                bincode::serde::encode_into_std_write(Request::Shutdown, &mut BufWriter::new(&tcp_stream), CONFIG).unwrap();
+            for session in &mut sessions {
+                if let Err(e) = session.link.send(MessageKind::Request, session.codec.as_mut(), &Request::Shutdown) {
+                    log::error!("Failed to send shutdown, {e}");
+                }
+
+                if let Err(e) = session.link.close() {
+                    log::error!("Failed to close the link, {e}");
+                }
             }
 
             log::debug!("Plugin thread is finishing");
@@ -220,6 +288,7 @@ impl Plugin for RapierPhysicsPlugin {
             SystemStage::parallel()
                 .with_system(systems::init_rigid_bodies)
                 .with_system(systems::init_colliders.after(systems::init_rigid_bodies))
+                .with_system(systems::request_snapshot.before(systems::send_context))
                 .with_system(systems::send_context.after(systems::init_colliders)),
         );
 
@@ -230,3 +299,114 @@ impl Plugin for RapierPhysicsPlugin {
         );
     }
 }
+
+/// Connection to one physics server, all of the world or one region of it.
+struct Session {
+    link: Box<dyn Transport>,
+    codec: Box<dyn Codec>,
+    negotiated: (CodecKind, CodecContext),
+    clock: ClockSync,
+    /// When the latest request left, in microseconds on the client's clock.
+    sent_at: u64,
+}
+
+impl Session {
+    /// Connects to the server at the address, following the broker if it is one, and completes the
+    /// handshake. The link is used for the first attempt if given.
+    fn open(transport: &TransportKind, mut address: String, settings: &Settings, mut link: Option<Box<dyn Transport>>) -> Self {
+        let (requested, requested_context, checksum) = match &settings.physics_plugin {
+            PhysicsPlugin::Server { codec, codec_context, checksum, .. } => (*codec, *codec_context, *checksum),
+            PhysicsPlugin::Default => (CodecKind::None, CodecContext::Reset, false),
+        };
+
+        let (link, negotiated) = loop {
+            let mut link = match link.take() {
+                Some(link) => link,
+                None => {
+                    let link = shared::transport::connect(transport, &address).unwrap();
+                    log::debug!("{} connection is established", transport);
+                    link
+                }
+            };
+
+            link.set_checksum(checksum);
+            link.send(MessageKind::Settings, &mut NoneCodec, settings).unwrap();
+
+            match link.recv(MessageKind::Handshake, &mut NoneCodec).unwrap() {
+                (Handshake::Accepted { codec, codec_context }, _) => break (link, (codec, codec_context)),
+                (Handshake::Redirect { address: server }, _) => {
+                    log::debug!("broker at {address} routed the session to {server}");
+                    link.close().unwrap();
+                    address = server;
+                }
+                (Handshake::Rejected(rejection), _) => panic!("physics server rejected the connection, {rejection}"),
+            }
+        };
+        log::debug!("requested {} codec with {} context, server runs {} with {}", requested, requested_context, negotiated.0, negotiated.1);
+
+        // The plugin emulates the link towards the client, the server the one towards it.
+        let link = match &settings.physics_plugin {
+            PhysicsPlugin::Server { network: Some(network), .. } => shared::transport::emulate(link, network.clone()),
+            _ => link,
+        };
+
+        Session { link, codec: negotiated.0.build(negotiated.1), negotiated, clock: ClockSync::default(), sent_at: 0 }
+    }
+
+    /// Moves the session to the target of the migration, or keeps it here if the target does not take it.
+    fn migrate(&mut self, transport: &TransportKind, migration: &Migration, settings: &Settings) -> Result<(), FrameError> {
+        self.link.send(MessageKind::Request, self.codec.as_mut(), &Request::Migrate { endpoint: migration.endpoint() })?;
+
+        match self.link.recv(MessageKind::Migration, &mut NoneCodec)? {
+            (Moved::Ready { ticket }, _) => {
+                if let Err(e) = self.link.close() {
+                    log::error!("Failed to close the link, {e}");
+                }
+
+                let mut settings = settings.clone();
+                if let PhysicsPlugin::Server { resume, .. } = &mut settings.physics_plugin {
+                    *resume = Some(ticket);
+                }
+
+                *self = Session::open(transport, migration.address.clone(), &settings, None);
+                log::debug!("session moved to {}", migration.address);
+            }
+            (Moved::Failed(rejection), _) => log::warn!("session stays on the server, {rejection}"),
+        }
+
+        Ok(())
+    }
+
+    fn receive(&mut self, downlink: &mut NetworkLog, comp_time: &mut TimeLog) -> Result<(SyncContext, Log, Latency), FrameError> {
+        let (Response::SyncContext(mut ctx), stats) = self.link.recv(MessageKind::Response, self.codec.as_mut())?;
+        let arrived = clock::now() - stats.elapsed as u64;
+        let (log, _) = self.link.recv::<Log>(MessageKind::Log, &mut NoneCodec)?;
+
+        let latency = self.clock.update(Exchange {
+            sent: self.sent_at,
+            received: log.received_at,
+            responded: log.responded_at,
+            arrived,
+        });
+
+        downlink.raw += stats.raw;
+        downlink.compressed += stats.compressed;
+        comp_time.decompress += stats.elapsed;
+
+        // Transforms that did not make it keep their last state until the next snapshot.
+        if self.link.supports_snapshots() {
+            let chunks = self.link.recv_snapshot(SNAPSHOT_WAIT)?;
+
+            let len = chunks.iter().map(|chunk| chunk.len() as u64).sum::<u64>();
+            downlink.raw += len;
+            downlink.compressed += len;
+
+            match shared::response::decode_snapshot(chunks) {
+                Ok(transforms) => ctx.transforms = transforms,
+                Err(e) => log::warn!("Dropping a malformed snapshot, {e}"),
+            }
+        }
+
+        Ok((ctx, log, latency))
+    }
+}
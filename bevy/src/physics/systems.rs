@@ -1,8 +1,9 @@
 use bevy_ecs::{
     prelude::Entity,
     query::Without,
-    system::{Commands, Query, Res, ResMut},
+    system::{Commands, Local, Query, Res, ResMut},
 };
+use bevy_input::{prelude::KeyCode, Input};
 use bevy_log::info_span;
 use bevy_rapier3d::{
     prelude::{
@@ -17,11 +18,11 @@ use bevy_rapier3d::{
 };
 use bevy_time::Time;
 use bevy_transform::{prelude::{GlobalTransform, Transform}, TransformBundle};
-use shared::{request::{Request, SyncContext}, response::Response};
+use shared::{request::{Request, SyncContext}, response::Response, settings::{PhysicsPlugin, Settings}};
 
 use crate::bench::PluginLog;
 
-use super::plugin::{RequestSender, ResponseReceiver};
+use super::plugin::{RequestSender, ResponseReceiver, SceneEntities};
 
 pub type RigidBodyComponents<'a> = (
     Entity,
@@ -245,25 +246,59 @@ pub fn init_colliders(
 
 pub fn send_context(
     time: Res<Time>,
+    mut frames: Local<u64>,
     mut rigid_bodies: ResMut<super::plugin::RigidBody>,
     mut colliders: ResMut<super::plugin::Collider>,
     request: Res<RequestSender>,
+    settings: Res<Settings>,
 ) {
     log::debug!("sending context");
 
+    // Counted rather than derived from the time, which drifts with the length of the frames.
+    *frames += 1;
+    let frame = match &settings.physics_plugin {
+        PhysicsPlugin::Server { lockstep: Some(_), .. } => Some(*frames),
+        _ => None,
+    };
+
     request
         .0
         .send(Request::SyncContext(SyncContext {
             rigid_bodies: std::mem::replace(&mut rigid_bodies.0, Vec::new()),
             colliders: std::mem::replace(&mut colliders.0, Vec::new()),
             delta_seconds: time.delta_seconds(),
+            frame,
+            handoffs: Vec::new(),
+            ghosts: Vec::new(),
         }))
         .unwrap();
 }
 
-pub fn writeback_rigid_bodies(mut commands: Commands, response: Res<ResponseReceiver>, mut log: ResMut<PluginLog>) {
+/// Asks the servers to save the state of the session when F5 is pressed.
+pub fn request_snapshot(keys: Option<Res<Input<KeyCode>>>, request: Res<RequestSender>) {
+    if keys.is_some_and(|keys| keys.just_pressed(KeyCode::F5)) {
+        request.0.send(Request::Snapshot).unwrap();
+    }
+}
+
+/// Entity of the id in a response, none if the scene generated by the server has no such body.
+fn resolve_entity(scene_entities: Option<&SceneEntities>, id: u64) -> Option<Entity> {
+    match scene_entities {
+        Some(scene_entities) => scene_entities.0.get(id as usize).copied(),
+        None => Some(Entity::from_bits(id)),
+    }
+}
+
+pub fn writeback_rigid_bodies(
+    mut commands: Commands,
+    response: Res<ResponseReceiver>,
+    mut log: ResMut<PluginLog>,
+    scene_entities: Option<Res<SceneEntities>>,
+) {
     log::debug!("writing back");
 
+    let scene_entities = scene_entities.as_deref();
+
     let _span = info_span!("writeback", name = "physics").entered();
     match response.0.recv().unwrap() {
         (Response::SyncContext(sync_context), plugin_log) => {
@@ -272,21 +307,24 @@ pub fn writeback_rigid_bodies(mut commands: Commands, response: Res<ResponseRece
             let _span = info_span!("response_received", name = "physics").entered();
 
             for (entity, handle) in sync_context.rigid_body_handles {
-                commands
-                    .entity(Entity::from_bits(entity))
-                    .insert(RapierRigidBodyHandle(handle));
+                match resolve_entity(scene_entities, entity).and_then(|entity| commands.get_entity(entity)) {
+                    Some(mut entity) => { entity.insert(RapierRigidBodyHandle(handle)); }
+                    None => log::warn!("rigid body handle for unknown entity {entity}"),
+                }
             }
 
             for (entity, handle) in sync_context.collider_handles {
-                commands
-                    .entity(Entity::from_bits(entity))
-                    .insert(RapierColliderHandle(handle));
+                match resolve_entity(scene_entities, entity).and_then(|entity| commands.get_entity(entity)) {
+                    Some(mut entity) => { entity.insert(RapierColliderHandle(handle)); }
+                    None => log::warn!("collider handle for unknown entity {entity}"),
+                }
             }
 
             for (entity, transform) in sync_context.transforms {
-                commands
-                    .entity(Entity::from_bits(entity))
-                    .insert(TransformBundle::from(transform));
+                match resolve_entity(scene_entities, entity).and_then(|entity| commands.get_entity(entity)) {
+                    Some(mut entity) => { entity.insert(TransformBundle::from(transform)); }
+                    None => log::warn!("transform for unknown entity {entity}"),
+                }
             }
         }
     }
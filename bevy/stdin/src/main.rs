@@ -4,7 +4,7 @@ use bevy_input::prelude::KeyCode;
 use console::{Term, Key};
 
 fn main() {
-    input::init("127.0.0.1:4001".to_string());
+    input::init(Some("127.0.0.1:4001".to_string()));
 
     let term = Term::stdout();
     let mut pressed_keys: HashSet<KeyCode> = HashSet::new();
@@ -6,7 +6,7 @@ import subprocess
 import multiprocessing
 
 iterations = range(1) # 2
-plugins = ['server_none'] # ['server_none', 'server_1', 'server_3', 'server_9'] # ['default', 'server_none', 'server_2', 'server_9']
+plugins = ['server_none'] # ['server_none', 'server_deflate_1', 'server_deflate_3', 'server_zstd_3', 'server_lz4'] # ['default', 'server_none', 'server_deflate_2', 'server_deflate_9']
 num_objects = [500] # [500, 1000, 2000, 4000, 8000]
 shapes = ['ball'] # ['ball', 'capsule', 'cuboid', 'complex']
 
@@ -32,11 +32,15 @@ def main():
                 for i in iterations:
                     configs.append((i, f'{plugin}_{num_object}_{shape}'))
 
+    # test-case-gen names every config after its position in the matrix and its settings, such as
+    # 007_server_none_500_ball, and several of them may share the settings part.
+    files = {}
+    for file in sorted(os.listdir(configs_path)):
+        files.setdefault(file.split('_', 1)[-1], []).append(file)
+    configs = [(iteration, file) for (iteration, config) in configs for file in files.get(config, [])]
+
     run_config_num = 0
     for (iteration, config) in configs:
-        if not os.path.isfile(f'{configs_path}/{config}'):
-            continue
-
         run_config_num += 1
 
         print(f'Running the config {config} iter {iteration}')
@@ -45,10 +49,19 @@ def main():
             bevy_output = open(f'{output_path}/{run_config_num}', mode='w')
             bench_output = open(f'{bench_output_path}/{run_config_num}', mode='w')
         else:
-            bevy_output = open(f'{output_path}/{config}_{iteration}', mode='w')
+            bevy_output = open(f'{output_path}/{config}_{iteration}.log', mode='w')
             bench_output = open(f'{bench_output_path}/{config}_{iteration}', mode='w')
 
-        handle = subprocess.Popen([program, f'{configs_path}/{config}'], stdout=bevy_output)
+        # The physics server takes its options from the command line, the client reads the config and
+        # writes the rows and, next to them as .summary.ron, the summary of the run itself.
+        if physics_program:
+            args = [program]
+        else:
+            args = [
+                program, f'{configs_path}/{config}',
+                '--output', f'{output_path}/{config}_{iteration}',
+            ]
+        handle = subprocess.Popen(args, stdout=bevy_output)
         top_handle = subprocess.Popen(['top', '-p', f'{handle.pid}', '-b', '-d' '0.05'], stdout=subprocess.PIPE)
 
         start_time = int(time.time() * 1000)
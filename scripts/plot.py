@@ -10,7 +10,7 @@ import math
 colors = [(0.8, 0.3, 0.3), (1, 0.6, 0.3), (0.3, 0.8, 0.3), (0.3, 0.3, 0.8)]
 
 plugin_labels = ['Default', 'Edge with No Compression', 'Edge with Level 2 Compression', 'Edge with Level 9 Compression']
-plugins = ['default', 'server_none', 'server_2', 'server_9'] # ['default', 'server_none', 'server_2', 'server_9']
+plugins = ['default', 'server_none', 'server_deflate_2', 'server_deflate_9'] # ['default', 'server_none', 'server_deflate_2', 'server_deflate_9']
 num_objects = [500, 1000, 2000, 4000, 8000]
 shapes = ['ball', 'cuboid', 'capsule', 'complex']
 
@@ -32,7 +32,7 @@ def main():
             for num_object in num_objects:
                 config = f'{plugin}_{num_object}_{shape}'
                 try:
-                    dataframe = pandas.read_csv(f'{input_dir}/{config}')
+                    dataframe = pandas.read_csv(f'{input_dir}/{config}', comment='#')
                 except FileNotFoundError:
                     print(f'file {input_dir}/{config} could not be found')
                     fps_line.append(0)
@@ -106,7 +106,7 @@ def main():
         for num_object in num_objects:
             config = f'{plugin}_{num_object}_{shape}'
             try:
-                dataframe = pandas.read_csv(f'{input_dir}/{config}')
+                dataframe = pandas.read_csv(f'{input_dir}/{config}', comment='#')
             except FileNotFoundError:
                 print(f'file {input_dir}/{config} could not be found')
                 network_line.append(0)
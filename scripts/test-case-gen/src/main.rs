@@ -1,102 +1,82 @@
-use std::{fmt::Display, io::Write};
+use std::path::Path;
 
-use serde::{Deserialize, Serialize};
+use shared::matrix::{Matrix, OPTION_AXES};
 
-#[derive(Clone, Deserialize, Serialize)]
-pub enum PhysicsPlugin {
-    Default,
-    Server {
-        compress: Option<u32>,
-        address: String,
-    },
+// call signature ./test-case-gen <matrix> <output-dir>, the matrix in RON or in TOML if its name ends with .toml.
+// In TOML the axes are inline tables such as `axes = [{ NumObject = [500, 1000] }]`, and the axes
+// of options such as Network leave the value out with "None", as in `{ Network = ["None", { ... }] }`.
+// Every case is written to a file named after its position in the matrix and its settings, such as
+// 007_server_zstd_3_500_ball.
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    if args.len() < 3 {
+        fail("usage: test-case-gen <matrix> <output-dir>".to_string());
+    }
+
+    let matrix_path = Path::new(&args[1]);
+    let output_dir = Path::new(&args[2]);
+
+    let matrix = std::fs::read_to_string(matrix_path)
+        .unwrap_or_else(|e| fail(format!("failed to read the matrix {}, {e}", matrix_path.display())));
+    let matrix: Matrix = match matrix_path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => from_toml(&matrix),
+        _ => ron::from_str(&matrix).unwrap_or_else(|e| fail(format!("invalid matrix {}, {e}", matrix_path.display()))),
+    };
+
+    let cases = matrix.cases();
+
+    for (index, settings) in cases.iter().enumerate() {
+        let path = output_dir.join(format!("{:03}_{}", index, settings));
+        let settings = ron::to_string(settings).unwrap_or_else(|e| fail(format!("failed to encode case {index}, {e}")));
+
+        std::fs::write(&path, settings).unwrap_or_else(|e| fail(format!("failed to write {}, {e}", path.display())));
+    }
+
+    println!("{} cases written to {}", cases.len(), output_dir.display());
 }
 
-impl Display for PhysicsPlugin {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            PhysicsPlugin::Default => f.write_str("default"),
-            PhysicsPlugin::Server { compress: None, .. } => f.write_str("server_none"),
-            PhysicsPlugin::Server {
-                compress: Some(compress),
-                ..
-            } => f.write_str(format!("server_{}", compress).as_str()),
+/// Reads the matrix from TOML, through JSON to give the "None" of the axes of options a value.
+fn from_toml(matrix: &str) -> Matrix {
+    let matrix: toml::Value = toml::from_str(matrix).unwrap_or_else(|e| fail(format!("invalid matrix, {e}")));
+    let mut matrix = serde_json::to_value(matrix).unwrap_or_else(|e| fail(format!("invalid matrix, {e}")));
+
+    if let Some(axes) = matrix.get_mut("axes").and_then(|axes| axes.as_array_mut()) {
+        axes.iter_mut().for_each(none_to_null);
+    }
+
+    for filters in ["exclude", "only"] {
+        if let Some(filters) = matrix.get_mut(filters).and_then(|filters| filters.as_array_mut()) {
+            filters
+                .iter_mut()
+                .filter_map(|filter| filter.as_array_mut())
+                .flatten()
+                .for_each(none_to_null);
         }
     }
-}
 
-#[derive(Clone, Deserialize, Serialize)]
-pub enum Room {
-    Open,
-    Closed,
+    serde_json::from_value(matrix).unwrap_or_else(|e| fail(format!("invalid matrix, {e}")))
 }
 
-#[derive(Clone, Deserialize, Serialize)]
-pub struct Scene {
-    pub camera: (f32, f32, f32),
-    pub num_object: usize,
-    pub shape: String,
-    pub restitution: f32,
-    pub room: Room,
-    pub ccd: bool,
-}
+/// Turns the "None" values of an axis of options into nulls, which deserialize as none.
+fn none_to_null(axis: &mut serde_json::Value) {
+    let Some(axis) = axis.as_object_mut() else {
+        return;
+    };
 
-#[derive(Clone, Deserialize, Serialize)]
-pub struct Settings {
-    pub tracing_level: Option<String>,
-    pub headless: bool,
-    pub physics_plugin: PhysicsPlugin,
-    pub bench_length: f32,
-    pub scene: Scene,
-}
+    for (name, values) in axis.iter_mut() {
+        if !OPTION_AXES.contains(&name.as_str()) {
+            continue;
+        }
 
-fn main() {
-    let output_dir = std::env::args().into_iter().collect::<Vec<String>>()[1].clone();
-
-    let plugins = [
-        PhysicsPlugin::Default,
-        PhysicsPlugin::Server {
-            compress: None,
-            address: "192.168.1.240:4001".to_string(),
-        },
-        PhysicsPlugin::Server {
-            compress: Some(1),
-            address: "192.168.1.240:4001".to_string(),
-        },
-        PhysicsPlugin::Server {
-            compress: Some(3),
-            address: "192.168.1.240:4001".to_string(),
-        },
-    ];
-
-    let num_objects = [500, 1000, 2000, 4000, 8000];
-
-    let shapes = ["ball", "capsule", "cuboid", "complex"];
-
-    for plugin in plugins {
-        for num_object in num_objects {
-            for shape in shapes {
-                let mut file =
-                    std::fs::File::create(format!("{}/{}_{}_{}", output_dir, plugin, num_object, shape)).unwrap();
-                file.write(
-                    ron::to_string(&Settings {
-                        tracing_level: None,
-                        headless: true,
-                        physics_plugin: plugin.clone(),
-                        bench_length: 30.0,
-                        scene: Scene {
-                            camera: (0.0, 80.0, 260.0),
-                            num_object,
-                            shape: shape.to_string(),
-                            restitution: 0.0,
-                            room: Room::Open,
-                            ccd: false,
-                        },
-                    })
-                    .unwrap()
-                    .as_bytes(),
-                )
-                .unwrap();
+        for value in values.as_array_mut().into_iter().flatten() {
+            if value.as_str() == Some("None") {
+                *value = serde_json::Value::Null;
             }
         }
     }
 }
+
+fn fail(message: String) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
+}